@@ -0,0 +1,766 @@
+use flate2::read::GzDecoder;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use std::thread;
+use tar::EntryType;
+use zip::ZipArchive;
+use zip::result::ZipError;
+
+use crate::commands::build::store_manifest;
+use crate::error::{Error, Result};
+use crate::filter::{IgnoreRules, PathFilter, RUSHIGNORE};
+use crate::hashers::chunking::{FileHash, hash_content};
+use crate::hashers::utils::Digest;
+use crate::utils::{
+    BuildOptions, BuildOutput, EntryKind, Leaf, MemoryManifest, Node, SymlinkMode, manifest_root,
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+/// Local file header, or end of central directory for an empty zip
+const ZIP_MAGICS: [&[u8]; 2] = [b"PK\x03\x04", b"PK\x05\x06"];
+/// POSIX tar headers have `ustar` at this offset
+const USTAR_OFFSET: usize = 257;
+const USTAR_MAGIC: &[u8] = b"ustar";
+/// Same limit as Linux before it gives up on a chain of links
+const MAX_LINK_HOPS: usize = 40;
+
+/// Suffixes dropped from the archive file name to name the root node
+const ARCHIVE_SUFFIXES: [&str; 6] = [".tar.gz", ".tar.zst", ".tgz", ".tzst", ".tar", ".zip"];
+
+#[derive(Clone, Copy)]
+enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+/// Format of the archive at `path`, from its first bytes. `None` when it
+/// does not look like one.
+fn detect(path: &Path) -> Result<Option<ArchiveFormat>> {
+    let mut head = Vec::new();
+    File::open(path)?
+        .take((USTAR_OFFSET + USTAR_MAGIC.len()) as u64)
+        .read_to_end(&mut head)?;
+    Ok(if head.starts_with(GZIP_MAGIC) {
+        Some(ArchiveFormat::TarGz)
+    } else if head.starts_with(ZSTD_MAGIC) {
+        Some(ArchiveFormat::TarZst)
+    } else if head.get(USTAR_OFFSET..) == Some(USTAR_MAGIC) {
+        Some(ArchiveFormat::Tar)
+    } else if ZIP_MAGICS.iter().any(|m| head.starts_with(m)) {
+        Some(ArchiveFormat::Zip)
+    } else {
+        None
+    })
+}
+
+/// Whether `path` is a `.tar`, `.tar.gz`, `.tar.zst` or `.zip` archive
+pub fn is_archive(path: &Path) -> Result<bool> {
+    Ok(path.is_file() && detect(path)?.is_some())
+}
+
+fn invalid(path: &Path, reason: impl Into<String>) -> Error {
+    Error::InvalidArchive {
+        path: path.to_path_buf(),
+        reason: reason.into(),
+    }
+}
+
+fn zip_error(path: &Path, err: ZipError) -> Error {
+    match err {
+        ZipError::Io(err) => Error::Io(err),
+        err => invalid(path, err.to_string()),
+    }
+}
+
+/// Name of the root node: the archive file name without its extension, which
+/// usually is the name of the extracted folder
+fn archive_name(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    ARCHIVE_SUFFIXES
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .map(str::to_string)
+        .unwrap_or(name)
+}
+
+/// Path of a member relative to the extraction folder. Like tar, leading `/`
+/// and `.` components are dropped, `None` is the folder itself.
+fn member_path(archive: &Path, path: &Path) -> Result<Option<PathBuf>> {
+    let mut rel = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => rel.push(name),
+            Component::ParentDir => {
+                return Err(invalid(
+                    archive,
+                    format!("{} points outside of the archive", path.display()),
+                ));
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    Ok((!rel.as_os_str().is_empty()).then_some(rel))
+}
+
+fn is_rushignore(rel: &Path) -> bool {
+    rel.file_name().is_some_and(|n| n == RUSHIGNORE)
+}
+
+fn open_tar(path: &Path, format: ArchiveFormat) -> Result<tar::Archive<Box<dyn Read>>> {
+    let file = BufReader::new(File::open(path)?);
+    let reader: Box<dyn Read> = match format {
+        ArchiveFormat::TarGz => Box::new(GzDecoder::new(file)),
+        ArchiveFormat::TarZst => Box::new(zstd::Decoder::with_buffer(file)?),
+        _ => Box::new(file),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+fn open_zip(path: &Path) -> Result<ZipArchive<BufReader<File>>> {
+    ZipArchive::new(BufReader::new(File::open(path)?)).map_err(|e| zip_error(path, e))
+}
+
+/// Hash the zip members at `indices`, each worker reading the archive with
+/// its own handle. The hashes are in the order of `indices`.
+fn hash_zip_members(path: &Path, indices: &[usize], opts: &BuildOptions) -> Result<Vec<FileHash>> {
+    let next = AtomicUsize::new(0);
+    let abort = AtomicBool::new(false);
+    let results: Vec<Result<Vec<(usize, FileHash)>>> = thread::scope(|s| {
+        let handles: Vec<_> = (0..opts.num_workers.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut zip = open_zip(path)?;
+                    let mut hashes = Vec::new();
+                    while !abort.load(Relaxed) {
+                        let next = next.fetch_add(1, Relaxed);
+                        if next >= indices.len() {
+                            break;
+                        }
+                        let hash = zip
+                            .by_index(indices[next])
+                            .map_err(|e| zip_error(path, e))
+                            .and_then(|mut entry| {
+                                let size = entry.size();
+                                hash_content(
+                                    &mut entry,
+                                    size,
+                                    &opts.method,
+                                    opts.bytes_to_hash,
+                                    opts.buffer_size,
+                                    opts.chunking.as_ref(),
+                                    opts.sampling.as_ref(),
+                                )
+                            });
+                        match hash {
+                            Ok(hash) => hashes.push((next, hash)),
+                            Err(err) => {
+                                // A broken member fails the build, stop the others
+                                abort.store(true, Relaxed);
+                                return Err(err);
+                            }
+                        }
+                    }
+                    Ok(hashes)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+
+    let mut hashes = vec![None; indices.len()];
+    for result in results {
+        for (i, hash) in result? {
+            hashes[i] = Some(hash);
+        }
+    }
+    Ok(hashes
+        .into_iter()
+        .map(|h| h.expect("every member hashed"))
+        .collect())
+}
+
+enum Member {
+    File(FileHash),
+    /// A file not hashed yet, by its index in the archive. Only the ones
+    /// that end up in the tree are hashed.
+    Pending(usize),
+    Dir,
+    Symlink(PathBuf),
+}
+
+/// The hashed members of an archive, keyed by their path relative to the
+/// extraction folder, "" being the folder itself
+struct Members {
+    entries: HashMap<PathBuf, Member>,
+    children: HashMap<PathBuf, BTreeSet<String>>,
+    /// Content of the `.rushignore` files, by directory
+    rushignores: HashMap<PathBuf, String>,
+}
+
+impl Members {
+    fn new() -> Self {
+        Members {
+            entries: HashMap::from([(PathBuf::new(), Member::Dir)]),
+            children: HashMap::new(),
+            rushignores: HashMap::new(),
+        }
+    }
+
+    /// Stream a tar archive once, hashing the content of the files that the
+    /// rules read so far do not ignore. A `.rushignore` can come after the
+    /// members it applies to, the files the tree needs in the end are hashed
+    /// by `hash_pending`.
+    fn read_tar(path: &Path, format: ArchiveFormat, opts: &BuildOptions) -> Result<Self> {
+        let rules = IgnoreRules::new(PathFilter::new(&opts.patterns)?);
+        let mut members = Members::new();
+        for (index, entry) in open_tar(path, format)?.entries()?.enumerate() {
+            let mut entry = entry?;
+            let Some(rel) = member_path(path, &entry.path()?)? else {
+                continue;
+            };
+            // Same as in a folder, a manifest stored inside is not data
+            if rel.components().any(|c| c.as_os_str() == ".rush") {
+                continue;
+            }
+
+            let member = match entry.header().entry_type() {
+                EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                    if is_rushignore(&rel) {
+                        members.read_rushignore(&rel, &mut entry, opts)?
+                    } else if members.is_ignored(path, &rules, &rel)? {
+                        Member::Pending(index)
+                    } else {
+                        let size = entry.size();
+                        Member::File(hash_content(
+                            &mut entry,
+                            size,
+                            &opts.method,
+                            opts.bytes_to_hash,
+                            opts.buffer_size,
+                            opts.chunking.as_ref(),
+                            opts.sampling.as_ref(),
+                        )?)
+                    }
+                }
+                EntryType::Directory => Member::Dir,
+                EntryType::Symlink => match entry.link_name()? {
+                    Some(target) => Member::Symlink(target.into_owned()),
+                    None => return Err(invalid(path, format!("{} has no target", rel.display()))),
+                },
+                // Extracted as a copy of a previous member
+                EntryType::Link => {
+                    let target = entry.link_name()?.unwrap_or_default().into_owned();
+                    let source = member_path(path, &target)?.unwrap_or_default();
+                    match members.entries.get(&source) {
+                        Some(Member::File(hash)) => Member::File(hash.clone()),
+                        Some(Member::Pending(index)) => Member::Pending(*index),
+                        _ => {
+                            return Err(invalid(
+                                path,
+                                format!("{} links to a missing file", rel.display()),
+                            ));
+                        }
+                    }
+                }
+                // Devices and fifos, skipped like in a folder
+                _ => continue,
+            };
+            members.insert(rel, member);
+        }
+        Ok(members)
+    }
+
+    /// Read a zip archive listing, the files are hashed by `hash_pending`
+    /// once the tree knows which ones it needs
+    fn read_zip(path: &Path, opts: &BuildOptions) -> Result<Self> {
+        let mut zip = open_zip(path)?;
+        let mut members = Members::new();
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i).map_err(|e| zip_error(path, e))?;
+            let Some(rel) = member_path(path, Path::new(entry.name()))? else {
+                continue;
+            };
+            if rel.components().any(|c| c.as_os_str() == ".rush") {
+                continue;
+            }
+
+            let member = if entry.is_dir() {
+                Member::Dir
+            } else if entry.is_symlink() {
+                // The target is stored as the content of the member
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                Member::Symlink(PathBuf::from(target))
+            } else if is_rushignore(&rel) {
+                members.read_rushignore(&rel, &mut entry, opts)?
+            } else {
+                Member::Pending(i)
+            };
+            members.insert(rel, member);
+        }
+        Ok(members)
+    }
+
+    /// Hash the pending files at `indices`, zip members with the worker
+    /// pool, tar members by streaming the archive again
+    fn hash_pending(
+        &mut self,
+        path: &Path,
+        format: ArchiveFormat,
+        indices: &BTreeSet<usize>,
+        opts: &BuildOptions,
+    ) -> Result<()> {
+        let hashes: HashMap<usize, FileHash> = match format {
+            ArchiveFormat::Zip => {
+                let indices: Vec<usize> = indices.iter().copied().collect();
+                let hashes = hash_zip_members(path, &indices, opts)?;
+                indices.into_iter().zip(hashes).collect()
+            }
+            _ => {
+                let mut hashes = HashMap::new();
+                for (index, entry) in open_tar(path, format)?.entries()?.enumerate() {
+                    let mut entry = entry?;
+                    if !indices.contains(&index) {
+                        continue;
+                    }
+                    let size = entry.size();
+                    let hash = hash_content(
+                        &mut entry,
+                        size,
+                        &opts.method,
+                        opts.bytes_to_hash,
+                        opts.buffer_size,
+                        opts.chunking.as_ref(),
+                        opts.sampling.as_ref(),
+                    )?;
+                    hashes.insert(index, hash);
+                }
+                hashes
+            }
+        };
+        // Hard links share the index of the member they copy
+        for member in self.entries.values_mut() {
+            if let Member::Pending(index) = member
+                && let Some(hash) = hashes.get(index)
+            {
+                *member = Member::File(hash.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the member at `rel` or one of its parents is ignored by the
+    /// rules read so far
+    fn is_ignored(&self, archive: &Path, rules: &IgnoreRules, rel: &Path) -> Result<bool> {
+        let mut rules = rules.clone();
+        let mut dir = PathBuf::new();
+        let mut components = rel.iter().peekable();
+        while let Some(name) = components.next() {
+            let rushignore = self.rushignores.get(&dir).map(String::as_str);
+            rules = rules.enter_with(&archive.join(&dir), rushignore)?;
+            dir.push(name);
+            let is_dir = components.peek().is_some();
+            if rules.is_ignored(&archive.join(&dir), &dir, is_dir) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Hash a `.rushignore` member and keep its rules for the tree
+    fn read_rushignore(
+        &mut self,
+        rel: &Path,
+        reader: &mut impl Read,
+        opts: &BuildOptions,
+    ) -> Result<Member> {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        let hash = hash_content(
+            content.as_slice(),
+            content.len() as u64,
+            &opts.method,
+            opts.bytes_to_hash,
+            opts.buffer_size,
+            opts.chunking.as_ref(),
+            opts.sampling.as_ref(),
+        )?;
+        let dir = rel.parent().unwrap_or(Path::new("")).to_path_buf();
+        let content = String::from_utf8_lossy(&content).into_owned();
+        self.rushignores.insert(dir, content);
+        Ok(Member::File(hash))
+    }
+
+    /// Add a member, along with the parent directories the extraction creates
+    /// when the archive does not list them
+    fn insert(&mut self, rel: PathBuf, member: Member) {
+        let mut path = rel.as_path();
+        while let Some(parent) = path.parent() {
+            let name = path
+                .file_name()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let added = self
+                .children
+                .entry(parent.to_path_buf())
+                .or_default()
+                .insert(name);
+            self.entries
+                .entry(parent.to_path_buf())
+                .or_insert(Member::Dir);
+            if !added {
+                break;
+            }
+            path = parent;
+        }
+        self.entries.insert(rel, member);
+    }
+
+    /// Member that the link `target`, found in `dir`, points to, following the
+    /// links on the way like in the extracted folder. `None` when the link
+    /// dangles or points outside of the archive.
+    fn resolve<'a>(&'a self, dir: &Path, target: &'a Path) -> Option<PathBuf> {
+        let mut current = dir.to_path_buf();
+        let mut pending: Vec<Component<'a>> = target.components().rev().collect();
+        let mut hops = 0;
+        while let Some(component) = pending.pop() {
+            match component {
+                Component::Normal(name) => {
+                    current.push(name);
+                    if let Member::Symlink(next) = self.entries.get(&current)? {
+                        hops += 1;
+                        if hops > MAX_LINK_HOPS {
+                            return None;
+                        }
+                        current.pop();
+                        pending.extend(next.components().rev());
+                    }
+                }
+                Component::ParentDir => {
+                    if !current.pop() {
+                        return None;
+                    }
+                }
+                Component::CurDir => {}
+                // Absolute links point to whatever is on the host
+                Component::RootDir | Component::Prefix(_) => return None,
+            }
+        }
+        Some(current)
+    }
+}
+
+/// Builds the tree of an archive the same way as the tree of its extracted
+/// folder, the ignore rules and symlink mode apply the same way.
+struct ArchiveTree<'a> {
+    archive: &'a Path,
+    members: &'a Members,
+    opts: &'a BuildOptions,
+    recorded_root: Option<PathBuf>,
+}
+
+impl ArchiveTree<'_> {
+    /// `source` is where the directory is in the archive, `rel` where it is in
+    /// the tree, they differ below a followed link
+    /// The pending files the tree needs are added to `pending`, the tree
+    /// has to be built again once they are hashed.
+    fn build_dir(
+        &self,
+        source: &Path,
+        rel: &Path,
+        parent: &IgnoreRules,
+        ancestors: &mut Vec<PathBuf>,
+        manifest: &mut MemoryManifest,
+        pending: &mut BTreeSet<usize>,
+    ) -> Result<Digest> {
+        let algorithm = self.opts.method.algorithm();
+        let rushignore = self.members.rushignores.get(source).map(String::as_str);
+        let rules = parent.enter_with(&self.archive.join(rel), rushignore)?;
+        ancestors.push(source.to_path_buf());
+
+        let mut children = Vec::new();
+        for name in self.members.children.get(source).into_iter().flatten() {
+            let path = source.join(name);
+            let sub = rel.join(name);
+            let member = &self.members.entries[&path];
+            let resolved = match member {
+                Member::Symlink(target) => self.members.resolve(source, target),
+                _ => Some(path.clone()),
+            };
+            let is_dir = resolved
+                .as_ref()
+                .is_some_and(|p| matches!(self.members.entries.get(p), Some(Member::Dir)));
+            if rules.is_ignored(&self.archive.join(&sub), &sub, is_dir) {
+                continue;
+            }
+
+            let mut file = |member: &Member| match member {
+                Member::Pending(index) => {
+                    pending.insert(*index);
+                    FileHash::new(algorithm.zero_digest())
+                }
+                Member::File(hash) => hash.clone(),
+                _ => unreachable!("not a file"),
+            };
+            let (hash, kind, target) = match member {
+                Member::File(_) | Member::Pending(_) => (file(member), EntryKind::File, None),
+                Member::Dir => {
+                    let hash = self.build_dir(&path, &sub, &rules, ancestors, manifest, pending)?;
+                    (FileHash::new(hash), EntryKind::Dir, None)
+                }
+                Member::Symlink(target) => {
+                    let text = Some(target.to_string_lossy().into_owned());
+                    let link = || {
+                        FileHash::new(algorithm.hash_bytes(target.as_os_str().as_encoded_bytes()))
+                    };
+                    match self.opts.symlinks {
+                        SymlinkMode::Skip => continue,
+                        SymlinkMode::Error => {
+                            return Err(Error::Symlink(self.archive.join(&sub)));
+                        }
+                        SymlinkMode::HashTargetPath => (link(), EntryKind::Symlink, text),
+                        SymlinkMode::Follow => {
+                            let resolved = resolved.as_ref();
+                            match resolved.and_then(|p| self.members.entries.get(p)) {
+                                Some(member @ (Member::File(_) | Member::Pending(_))) => {
+                                    (file(member), EntryKind::File, text)
+                                }
+                                Some(Member::Dir)
+                                    if !resolved.is_some_and(|p| ancestors.contains(p)) =>
+                                {
+                                    let target = resolved.expect("resolved directory");
+                                    let hash = self.build_dir(
+                                        target, &sub, &rules, ancestors, manifest, pending,
+                                    )?;
+                                    (FileHash::new(hash), EntryKind::Dir, text)
+                                }
+                                // Dangling, looping back to a parent or
+                                // outside of the archive
+                                _ => (link(), EntryKind::Symlink, text),
+                            }
+                        }
+                    }
+                }
+            };
+            children.push(Leaf {
+                name: name.clone(),
+                hash: hash.digest,
+                kind: Some(kind),
+                target,
+                stat: None,
+                chunks: hash.chunks,
+            });
+        }
+        ancestors.pop();
+
+        let root_hash = algorithm.merkle_root(&children, self.opts.schema_version)?;
+        let is_root = rel.as_os_str().is_empty();
+        let patterns = &self.opts.patterns;
+        let node = Node {
+            schema_version: self.opts.schema_version,
+            name: if is_root {
+                archive_name(self.archive)
+            } else {
+                rel.to_string_lossy().into_owned()
+            },
+            hash_method: self.opts.method.to_string(),
+            non_cryptographic: !self.opts.method.is_cryptographic(),
+            root_hash,
+            children,
+            bytes_to_hash: self.opts.bytes_to_hash,
+            chunking: self.opts.chunking,
+            sampling: self.opts.sampling,
+            patterns: (is_root && !patterns.is_empty()).then(|| patterns.clone()),
+            symlinks: is_root.then_some(self.opts.symlinks),
+            dataset_root: self.recorded_root.clone().filter(|_| is_root),
+        };
+        manifest.nodes.insert(rel.to_path_buf(), node);
+        Ok(root_hash)
+    }
+}
+
+/// Build the tree of an archive without extracting it. A tar archive is
+/// streamed once, the members of a zip archive are hashed in parallel. The
+/// root is the root of the folder the archive extracts to.
+pub(crate) fn build(path: &Path, opts: &BuildOptions) -> Result<BuildOutput> {
+    if opts.keep_going {
+        return Err(invalid(
+            path,
+            "--keep-going is not supported, a member that cannot be read fails the build",
+        ));
+    }
+    let Some(format) = detect(path)? else {
+        return Err(invalid(path, "not a tar, tar.gz, tar.zst or zip archive"));
+    };
+    let mut members = match format {
+        ArchiveFormat::Zip => Members::read_zip(path, opts)?,
+        format => Members::read_tar(path, format, opts)?,
+    };
+    let recorded_root = match opts.manifest_dir {
+        Some(_) => Some(fs::canonicalize(path)?),
+        None => None,
+    };
+
+    let rules = IgnoreRules::new(PathFilter::new(&opts.patterns)?);
+    // The first pass finds the files to hash, the second one has them all
+    let (root, mut manifest) = loop {
+        let tree = ArchiveTree {
+            archive: path,
+            members: &members,
+            opts,
+            recorded_root: recorded_root.clone(),
+        };
+        let mut manifest = MemoryManifest::default();
+        let mut pending = BTreeSet::new();
+        let root = tree.build_dir(
+            Path::new(""),
+            Path::new(""),
+            &rules,
+            &mut Vec::new(),
+            &mut manifest,
+            &mut pending,
+        )?;
+        if pending.is_empty() {
+            break (root, manifest);
+        }
+        members.hash_pending(path, format, &pending, opts)?;
+    };
+    if opts.store {
+        store_manifest(
+            &manifest,
+            &manifest_root(path, opts.manifest_dir.as_deref()),
+            opts,
+        )?;
+        manifest = MemoryManifest::default();
+    }
+
+    Ok(BuildOutput {
+        root,
+        manifest,
+        failed: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::DatasetHasher;
+    use crate::utils::{SymlinkMode, write_dataset};
+
+    /// Files, an empty folder and ignore rules
+    const LAYOUT: &[(&str, &str)] = &[
+        ("top.txt", "top"),
+        ("empty/", ""),
+        ("data/nested/leaf.txt", "leaf"),
+        ("data/blob.bin", "binary content"),
+        ("data/skipped.tmp", "ignored"),
+        ("data/.rushignore", "*.tmp\n"),
+    ];
+
+    /// `LAYOUT` in `dir`, with a link to a file
+    fn write_with_link(dir: &Path) {
+        write_dataset(dir, LAYOUT);
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("nested/leaf.txt", dir.join("data/link")).unwrap();
+    }
+
+    /// Same root from the archive as from the folder it was made of, for
+    /// every way of handling links and with and without excludes
+    fn assert_same_roots(archive: &Path, dir: &Path) {
+        for symlinks in [
+            SymlinkMode::Follow,
+            SymlinkMode::HashTargetPath,
+            SymlinkMode::Skip,
+        ] {
+            for exclude in [None, Some("*.bin"), Some("data/nested")] {
+                let build = |path: &Path| {
+                    let hasher = DatasetHasher::new(path).store(false).symlinks(symlinks);
+                    match exclude {
+                        Some(pattern) => hasher.exclude(pattern),
+                        None => hasher,
+                    }
+                    .build()
+                    .unwrap()
+                };
+                let expected = build(dir);
+                let actual = build(archive);
+                assert_eq!(actual.root, expected.root, "{symlinks:?} {exclude:?}");
+                assert_eq!(actual.manifest.nodes.len(), expected.manifest.nodes.len());
+            }
+        }
+    }
+
+    #[test]
+    fn tar_root_matches_the_extracted_folder() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("src");
+        write_with_link(&dir);
+
+        let tar_path = tmp.path().join("src.tar");
+        let mut tar = tar::Builder::new(File::create(&tar_path).unwrap());
+        tar.follow_symlinks(false);
+        tar.append_dir_all(".", &dir).unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+        assert_same_roots(&tar_path, &dir);
+
+        let tgz_path = tmp.path().join("src.tar.gz");
+        let gz = flate2::write::GzEncoder::new(
+            File::create(&tgz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tgz = tar::Builder::new(gz);
+        tgz.follow_symlinks(false);
+        tgz.append_dir_all(".", &dir).unwrap();
+        tgz.into_inner().unwrap().finish().unwrap();
+        assert_same_roots(&tgz_path, &dir);
+    }
+
+    /// Add the content of `dir` under `prefix`, links stored as links
+    fn zip_dir(zip: &mut zip::ZipWriter<File>, dir: &Path, prefix: &str) {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        for entry in fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            let name = format!("{prefix}{}", entry.file_name().to_string_lossy());
+            let file_type = entry.file_type().unwrap();
+            if file_type.is_symlink() {
+                let target = fs::read_link(entry.path()).unwrap();
+                zip.add_symlink(name, target.to_string_lossy(), options)
+                    .unwrap();
+            } else if file_type.is_dir() {
+                zip.add_directory(&name, options).unwrap();
+                zip_dir(zip, &entry.path(), &format!("{name}/"));
+            } else {
+                zip.start_file(name, options).unwrap();
+                std::io::copy(&mut File::open(entry.path()).unwrap(), zip).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn zip_root_matches_the_extracted_folder() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("src");
+        write_with_link(&dir);
+
+        let zip_path = tmp.path().join("src.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        zip_dir(&mut zip, &dir, "");
+        zip.finish().unwrap();
+        assert_same_roots(&zip_path, &dir);
+    }
+}
@@ -1,7 +1,13 @@
-use clap::{Parser, Subcommand, ValueHint};
+use clap::{Args, Parser, Subcommand, ValueHint};
 use std::path::PathBuf;
 
-use crate::hashers::utils::HashMethod;
+use rush::HashMethod;
+use rush::commands::diff::DiffFormat;
+use rush::filter::Patterns;
+use rush::format::ManifestFormat;
+use rush::hashers::chunking::Chunking;
+use rush::hashers::sampling::Sampling;
+use rush::utils::{MANIFEST_DIR_ENV, ManifestLayout, SymlinkMode};
 
 /// Simple tool to hash and compare your data
 #[derive(Parser, Debug)]
@@ -16,8 +22,9 @@ pub(crate) struct Cli {
 pub(crate) enum Command {
     /// Build a Merkle tree from path
     Build {
-        /// Root path of the folder to build a merkle tree for
-        #[arg(value_name = "PATH", value_hint = ValueHint::DirPath, required=true)]
+        /// Root path of the folder to build a merkle tree for, or an archive
+        /// (.tar, .tar.gz, .tar.zst, .zip)
+        #[arg(value_name = "PATH", value_hint = ValueHint::AnyPath, required=true)]
         path: PathBuf,
         /// The hashing function we want to use to hash
         #[arg(short, long, default_value_t = HashMethod::Md5)]
@@ -32,15 +39,139 @@ pub(crate) enum Command {
         /// Number of worker threads (default: 4)
         #[arg(long, short = 'w', default_value_t = 4)]
         num_workers: usize,
+        /// Only rehash files whose size, mtime, inode or ctime changed since
+        /// the previous build
+        #[arg(long)]
+        incremental: bool,
+        /// Finish the build when some files cannot be hashed and list them in
+        /// `.rush/errors.json` instead of failing
+        #[arg(long)]
+        keep_going: bool,
+        /// Split files into content-defined chunks and record them, each file
+        /// is hashed as the Merkle root of its chunks
+        #[arg(long)]
+        chunking: bool,
+        /// Average chunk size in bytes, chunks are between a quarter and four
+        /// times this size
+        #[arg(long, default_value_t = Chunking::DEFAULT_AVG_SIZE, requires = "chunking")]
+        chunk_size: u32,
+        /// Hash each file from its size, its head, its tail and evenly spaced
+        /// windows instead of its whole content
+        #[arg(long, conflicts_with_all = ["chunking", "bytes_to_hash"])]
+        sample: bool,
+        /// Size in bytes of each sampled window
+        #[arg(long, default_value_t = Sampling::DEFAULT_WINDOW_SIZE, requires = "sample")]
+        sample_window: u64,
+        /// Number of windows between the head and the tail
+        #[arg(long, default_value_t = Sampling::DEFAULT_WINDOWS, requires = "sample")]
+        sample_count: u32,
+        #[command(flatten)]
+        patterns: PatternArgs,
+        /// How symbolic links are hashed
+        #[arg(long, value_enum, default_value_t = SymlinkMode::Follow)]
+        symlinks: SymlinkMode,
+        /// Store the manifest in this folder instead of `PATH/.rush`
+        #[arg(long, env = MANIFEST_DIR_ENV, value_hint = ValueHint::DirPath)]
+        manifest_dir: Option<PathBuf>,
+        /// One `merkle.json` per directory, or a single indexed file
+        #[arg(long, value_enum, default_value_t = ManifestLayout::Tree)]
+        manifest_layout: ManifestLayout,
+        /// Encoding of the manifest, every reader detects it
+        #[arg(long, value_enum, default_value_t = ManifestFormat::Json)]
+        manifest_format: ManifestFormat,
     },
-    /// Compare the two Merkle trees from folder path
+    /// Compare the two Merkle trees from folder path. Exits with 0 when they
+    /// are identical, 1 when they differ and 2 on errors, like GNU diff.
     Diff {
-        /// Path to the first folder
-        #[arg(value_hint = ValueHint::DirPath)]
+        /// First dataset, exported manifest file, or folder to hash on the fly
+        #[arg(value_hint = ValueHint::AnyPath)]
         path1: PathBuf,
-        /// Path to the second folder
-        #[arg(value_hint = ValueHint::DirPath)]
+        /// Second dataset, exported manifest file, or folder to hash on the fly
+        #[arg(value_hint = ValueHint::AnyPath)]
         path2: PathBuf,
+        /// Hashing function for a folder without manifest (default: the one
+        /// of the other side, or md5)
+        #[arg(short, long)]
+        method: Option<HashMethod>,
+        /// Bytes to hash for a folder without manifest (default: the ones of
+        /// the other side, or 0 for the full content)
+        #[arg(long = "bh")]
+        bytes_to_hash: Option<u64>,
+        /// Number of worker threads (default: 4)
+        #[arg(long, short = 'w', default_value_t = 4)]
+        num_workers: usize,
+        #[command(flatten)]
+        patterns: PatternArgs,
+        /// Output format, json and ndjson carry the digests of each entry
+        #[arg(long, value_enum, default_value_t = DiffFormat::Text)]
+        format: DiffFormat,
+        /// Manifest stored outside its dataset, used for the operand it was
+        /// built from (repeatable). PATH1 and PATH2 can also be manifest
+        /// folders themselves.
+        #[arg(long, env = MANIFEST_DIR_ENV, value_hint = ValueHint::DirPath)]
+        manifest_dir: Vec<PathBuf>,
+        /// Print nothing, only set the exit status
+        #[arg(short, long, conflicts_with = "format")]
+        quiet: bool,
+        /// Report moved paths as removed and added
+        #[arg(long)]
+        no_renames: bool,
+    },
+    /// Check that a folder still matches its stored Merkle tree
+    Verify {
+        /// Root path of the folder or archive to verify
+        #[arg(value_name = "PATH", value_hint = ValueHint::AnyPath, required=true)]
+        path: PathBuf,
+        /// Folder of the manifest when not in `PATH/.rush`
+        #[arg(long, env = MANIFEST_DIR_ENV, value_hint = ValueHint::DirPath)]
+        manifest_dir: Option<PathBuf>,
+        /// Buffer size for read and hash operations.
+        #[arg(short, long = "bs", default_value_t = 8192)]
+        buffer_size: usize,
+        /// Number of worker threads (default: 4)
+        #[arg(long, short = 'w', default_value_t = 4)]
+        num_workers: usize,
+        #[command(flatten)]
+        patterns: PatternArgs,
+    },
+    /// Upgrade a manifest to the latest schema version, in place
+    Migrate {
+        /// Dataset, manifest folder or manifest file
+        #[arg(value_name = "PATH", required = true)]
+        path: PathBuf,
+        /// Folder of the manifest when not in `PATH/.rush`
+        #[arg(long, env = MANIFEST_DIR_ENV, value_hint = ValueHint::DirPath)]
+        manifest_dir: Option<PathBuf>,
+    },
+    /// Emit an inclusion proof for a single file of a built dataset
+    Prove {
+        /// Root path of the dataset or archive
+        #[arg(value_hint = ValueHint::AnyPath)]
+        dataset: PathBuf,
+        /// Path of the file relative to the dataset root
+        path: PathBuf,
+        /// Folder of the manifest when not in `DATASET/.rush`
+        #[arg(long, env = MANIFEST_DIR_ENV, value_hint = ValueHint::DirPath)]
+        manifest_dir: Option<PathBuf>,
+    },
+    /// Check that a file belongs to a dataset root using its proof
+    VerifyProof {
+        /// Proof emitted by `rush prove`
+        #[arg(value_hint = ValueHint::FilePath)]
+        proof: PathBuf,
+        /// Path to the file
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+        /// Hex encoded root hash of the published dataset
+        #[arg(long)]
+        root: String,
+        /// Accept schema version 1 proofs, whose roots do not commit to the
+        /// names of the files
+        #[arg(long)]
+        allow_v1: bool,
+        /// Buffer size for read and hash operations.
+        #[arg(short, long = "bs", default_value_t = 8192)]
+        buffer_size: usize,
     },
     /// Hash a single file
     Hash {
@@ -59,3 +190,23 @@ pub(crate) enum Command {
         buffer_size: usize,
     },
 }
+
+/// Gitignore-style patterns, on top of the `.rushignore` files
+#[derive(Args, Debug)]
+pub(crate) struct PatternArgs {
+    /// Leave out the paths matching the pattern (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+    /// Keep the paths matching the pattern even if excluded (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub include: Vec<String>,
+}
+
+impl From<PatternArgs> for Patterns {
+    fn from(args: PatternArgs) -> Self {
+        Patterns {
+            exclude: args.exclude,
+            include: args.include,
+        }
+    }
+}
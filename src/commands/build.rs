@@ -1,47 +1,182 @@
-use anyhow::{Ok, Result, bail};
-use rs_merkle::MerkleTree;
-
 use std::io::BufWriter;
 use std::path::{Path, PathBuf};
 
-use crate::hashers::blake3::Blake3Algorithm;
-use crate::hashers::md5::Md5Algorithm;
-use crate::hashers::sha256::Sha256Algorithm;
-use crate::hashers::utils::{Digest, DigestCompatibleHasher, HashMethod, hash_file};
-use crate::utils::{BuildConfig, Leaf, Node, rel_path_str};
+use crate::archive;
+use crate::error::{Error, Result};
+use crate::filter::{IgnoreRules, PathFilter};
+use crate::format::ManifestFormat;
+use crate::hashers::chunking::{FileHash, hash_file_content};
+use crate::hashers::utils::Digest;
+use crate::utils::{
+    BuildConfig, BuildOptions, BuildOutput, EntryKind, FailedFile, FileManifest, FileStat, Leaf,
+    ManifestLayout, MemoryManifest, Node, SymlinkMode, has_manifest, manifest_file, manifest_root,
+    open_manifest, rel_path_str, remove_stale, write_node_file,
+};
+use std::collections::HashMap;
 use std::fs;
-use std::fs::File;
+use std::fs::{File, Metadata};
 use std::{
-    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed},
     thread,
 };
 
-pub(crate) fn invoke(
-    path: &Path,
-    method: &HashMethod,
-    bytes_to_hash: u64,
-    buffer_size: usize,
-    num_workers: usize,
-) -> Result<()> {
-    if path.is_dir() {
-        let hash_root = generic_build(path, method, bytes_to_hash, buffer_size, num_workers)?;
-        println!("{}", hex::encode(hash_root));
+pub fn invoke(path: &Path, opts: &BuildOptions) -> Result<()> {
+    if path.is_dir() || archive::is_archive(path)? {
+        let output = generic_build(path, opts)?;
+        if !output.failed.is_empty() {
+            for f in &output.failed {
+                eprintln!("warning: failed to hash {}: {}", f.path.display(), f.error);
+            }
+            eprintln!(
+                "warning: {} file(s) left out of the tree, see {}",
+                output.failed.len(),
+                manifest_root(path, opts.manifest_dir.as_deref())
+                    .join("errors.json")
+                    .display()
+            );
+        }
+        println!("{}", hex::encode(output.root));
     } else {
-        anyhow::bail!("incorrect path: {}\n should be a directory", path.display());
+        return Err(Error::NotADirectory(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+fn setup_build(rush_root: &Path, layout: ManifestLayout, format: ManifestFormat) -> Result<()> {
+    fs::create_dir_all(rush_root)?;
+    // A single-file manifest is read first, a stale one must not hide the
+    // new manifest
+    for stale in ManifestFormat::ALL {
+        if layout == ManifestLayout::Tree || stale != format {
+            remove_stale(&rush_root.join(stale.manifest_file()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a manifest built in memory, for the builds that do not store the
+/// nodes as they go
+pub(crate) fn store_manifest(
+    manifest: &MemoryManifest,
+    rush_root: &Path,
+    opts: &BuildOptions,
+) -> Result<()> {
+    setup_build(rush_root, opts.layout, opts.format)?;
+    store_error_report(&[], rush_root)?;
+    match opts.layout {
+        ManifestLayout::Tree => {
+            for (rel, node) in &manifest.nodes {
+                write_node_file(node, &rush_root.join(rel), opts.format)?;
+            }
+            Ok(())
+        }
+        ManifestLayout::File => {
+            let file = rush_root.join(opts.format.manifest_file());
+            FileManifest::write(manifest, &file, opts.format)
+        }
+    }
+}
+
+/// Write the list of files that could not be hashed next to the manifest, or
+/// remove the stale one from a previous build.
+fn store_error_report(failed: &[FailedFile], rush_root: &Path) -> Result<()> {
+    let report = rush_root.join("errors.json");
+    if failed.is_empty() {
+        match fs::remove_file(&report) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)?,
+            _ => return Ok(()),
+        }
     }
+    let file = BufWriter::new(File::create(&report)?);
+    serde_json::to_writer_pretty(file, failed)?;
     Ok(())
 }
 
-fn setup_build(root: &Path) -> anyhow::Result<PathBuf> {
-    let rush_root = root.join(".rush");
-    fs::create_dir_all(&rush_root)?;
-    Ok(rush_root)
+/// State of the walk in one directory: the ignore rules in effect and the
+/// (dev, inode) of the directories above it, to detect symlink loops.
+#[derive(Clone)]
+struct DirContext {
+    rules: IgnoreRules,
+    ancestors: Vec<(u64, u64)>,
+}
+
+impl DirContext {
+    fn enter(&self, dir: &Path) -> Result<Self> {
+        let mut ancestors = self.ancestors.clone();
+        ancestors.extend(dir_id(&fs::metadata(dir)?));
+        Ok(DirContext {
+            rules: self.rules.enter(dir)?,
+            ancestors,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn dir_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// What an entry becomes in the tree
+enum Entry {
+    File(Metadata),
+    Dir,
+    /// Hashed from the link target path: `hash-target-path` mode, dangling
+    /// links and links looping back to a parent directory
+    Link,
+}
+
+/// Classify an entry according to the symlink mode, along with the link
+/// target when it is a symlink. Other entries like sockets are skipped.
+fn classify(
+    path: &Path,
+    ctx: &DirContext,
+    cfg: &BuildConfig,
+) -> Result<Option<(Entry, Option<PathBuf>)>> {
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.file_type().is_symlink() {
+        return Ok(if meta.is_file() {
+            Some((Entry::File(meta), None))
+        } else if meta.is_dir() {
+            Some((Entry::Dir, None))
+        } else {
+            None
+        });
+    }
+
+    let target = fs::read_link(path)?;
+    let entry = match cfg.symlinks {
+        SymlinkMode::Skip => return Ok(None),
+        SymlinkMode::Error => return Err(Error::Symlink(path.to_path_buf())),
+        SymlinkMode::HashTargetPath => Entry::Link,
+        SymlinkMode::Follow => match fs::metadata(path) {
+            Ok(m) if m.is_file() => Entry::File(m),
+            Ok(m) if m.is_dir() && !dir_id(&m).is_some_and(|id| ctx.ancestors.contains(&id)) => {
+                Entry::Dir
+            }
+            _ => Entry::Link,
+        },
+    };
+    Ok(Some((entry, Some(target))))
 }
 
-fn get_deterministic_entries(path: &Path) -> Result<Vec<PathBuf>> {
+fn get_deterministic_entries(
+    path: &Path,
+    rules: &IgnoreRules,
+    cfg: &BuildConfig,
+) -> Result<Vec<PathBuf>> {
     let mut entries: Vec<PathBuf> = fs::read_dir(path)?
         .filter_map(|e| e.ok().map(|e| e.path()))
         .filter(|p| p.file_name().is_none_or(|p| p != ".rush"))
+        .filter(|p| {
+            let rel = p.strip_prefix(&cfg.dataset_root).unwrap_or(p);
+            !rules.is_ignored(p, rel, p.is_dir())
+        })
         .collect();
 
     entries.sort();
@@ -49,57 +184,123 @@ fn get_deterministic_entries(path: &Path) -> Result<Vec<PathBuf>> {
     Ok(entries)
 }
 
-fn initialize(path: &Path, file_names: &mut Vec<PathBuf>) -> Result<()> {
-    let entries = get_deterministic_entries(path)?;
-    for entry in entries {
-        if entry.is_file() {
-            file_names.push(entry);
-        } else if entry.is_dir() {
-            initialize(&entry, file_names)?;
-        }
-    }
-    Ok(())
+/// A file collected during the first pass. `cached` holds the hash from the
+/// previous build when the file is unchanged since then, `failed` is set when
+/// a worker could not hash it.
+struct FileEntry {
+    path: PathBuf,
+    stat: FileStat,
+    cached: Option<FileHash>,
+    failed: bool,
 }
 
-fn store_node_to_disk(
-    node: &Node,
-    dataset_root: &Path,
+/// Load the node stored by the previous build for `path`, if it is
+/// compatible with the current configuration.
+fn previous_node(path: &Path, cfg: &BuildConfig) -> Option<Node> {
+    let rel = path.strip_prefix(&cfg.dataset_root).ok()?;
+    let node = cfg.previous.as_ref()?.node(rel).ok()?.into_owned();
+    (node.schema_version == cfg.schema_version
+        && node.hash_method == cfg.algorithm.name()
+        && node.bytes_to_hash == cfg.bytes_to_hash
+        && node.chunking == cfg.chunking
+        && node.sampling == cfg.sampling)
+        .then_some(node)
+}
+
+fn initialize(
     path: &Path,
-    rush_root: &Path,
+    parent: &DirContext,
+    files: &mut Vec<FileEntry>,
+    cfg: &BuildConfig,
 ) -> Result<()> {
-    let rel = path.strip_prefix(dataset_root)?;
-    let target_dir = rush_root.join(rel);
-
-    fs::create_dir_all(&target_dir)?;
+    let ctx = parent.enter(path)?;
+    let entries = get_deterministic_entries(path, &ctx.rules, cfg)?;
+    let previous = if cfg.incremental {
+        previous_node(path, cfg)
+    } else {
+        None
+    };
+    let previous: HashMap<&str, &Leaf> = previous
+        .iter()
+        .flat_map(|n| &n.children)
+        .map(|c| (c.name.as_str(), c))
+        .collect();
 
-    let file_path = target_dir.join("merkle.json");
-    let file = BufWriter::new(File::create(&file_path)?);
-    serde_json::to_writer_pretty(file, node)?;
+    for entry in entries {
+        match classify(&entry, &ctx, cfg)? {
+            Some((Entry::File(meta), _)) => {
+                let stat = FileStat::from_metadata(&meta);
+                // Reuse the previous hash only if the stat tuple did not move
+                let cached = entry
+                    .file_name()
+                    .and_then(|n| previous.get(n.to_string_lossy().as_ref()))
+                    .filter(|leaf| leaf.stat == Some(stat))
+                    .map(|leaf| FileHash {
+                        digest: leaf.hash,
+                        chunks: leaf.chunks.clone(),
+                    });
+                files.push(FileEntry {
+                    path: entry,
+                    stat,
+                    cached,
+                    failed: false,
+                });
+            }
+            Some((Entry::Dir, _)) => initialize(&entry, &ctx, files, cfg)?,
+            Some((Entry::Link, _)) | None => {}
+        }
+    }
     Ok(())
 }
 
-fn build_merkle_tree<H>(
+fn store_node_to_disk(node: &Node, path: &Path, cfg: &BuildConfig) -> Result<()> {
+    let rel = path.strip_prefix(&cfg.dataset_root)?;
+    write_node_file(node, &cfg.rush_root.join(rel), cfg.format)
+}
+
+fn build_merkle_tree(
     path: &Path,
-    hashes: &[Digest],
+    parent: &DirContext,
+    files: &[FileEntry],
+    hashes: &[FileHash],
     file_index: &mut usize,
     cfg: &BuildConfig,
-) -> Result<Digest>
-where
-    H: DigestCompatibleHasher,
-{
-    let entries = get_deterministic_entries(path)?;
+    manifest: &mut MemoryManifest,
+) -> Result<Digest> {
+    let ctx = parent.enter(path)?;
+    let entries = get_deterministic_entries(path, &ctx.rules, cfg)?;
     let mut children = Vec::new();
-    let mut merkle_tree = MerkleTree::<H>::new();
     for entry in entries {
-        let hash = {
-            if entry.is_file() {
-                let hash = hashes[*file_index];
+        let Some((class, target)) = classify(&entry, &ctx, cfg)? else {
+            continue;
+        };
+        let (hash, kind, stat, chunks) = match class {
+            Entry::File(_) => {
+                let hash = &hashes[*file_index];
+                let file = &files[*file_index];
                 *file_index += 1;
-                hash
-            } else if entry.is_dir() {
-                build_merkle_tree::<H>(&entry, hashes, file_index, cfg)?
-            } else {
-                bail!("neither file or folder")
+                if file.failed {
+                    // Reported by the build, it does not belong in the tree
+                    continue;
+                }
+                (
+                    hash.digest,
+                    EntryKind::File,
+                    Some(file.stat),
+                    hash.chunks.clone(),
+                )
+            }
+            Entry::Dir => {
+                let hash =
+                    build_merkle_tree(&entry, &ctx, files, hashes, file_index, cfg, manifest)?;
+                (hash, EntryKind::Dir, None, None)
+            }
+            Entry::Link => {
+                let target = target.as_deref().unwrap_or(Path::new(""));
+                let hash = cfg
+                    .algorithm
+                    .hash_bytes(target.as_os_str().as_encoded_bytes());
+                (hash, EntryKind::Symlink, None, None)
             }
         };
 
@@ -109,119 +310,341 @@ where
                 .map(|s| s.to_string_lossy().into_owned())
                 .unwrap_or_default(),
             hash,
+            kind: Some(kind),
+            target: target.map(|t| t.to_string_lossy().into_owned()),
+            stat,
+            chunks,
         });
+    }
 
-        // covert hash for merkle tree
-        let hash = H::from_digest(&hash)?;
-        merkle_tree.insert(hash);
+    let previous = if cfg.incremental {
+        previous_node(path, cfg)
+    } else {
+        None
+    };
+    if let Some(previous) = previous
+        .as_ref()
+        .filter(|_| !cfg.store || cfg.previous_stored)
+        && previous.children == children
+    {
+        // Nothing changed below this directory, the stored node is still
+        // valid. A manifest built in memory still needs it.
+        if !cfg.store {
+            let rel = path.strip_prefix(&cfg.dataset_root)?;
+            manifest.nodes.insert(rel.to_path_buf(), previous.clone());
+        }
+        return Ok(previous.root_hash);
     }
-    // Don't forget to commit the changes made by MerkleTree::insert
-    merkle_tree.commit();
-    let root = merkle_tree
-        .root()
-        .ok_or_else(|| anyhow::anyhow!("Merkle tree has no root (empty tree?)"))?;
 
-    let root_hash = H::to_digest(root);
+    let root_hash = match &previous {
+        // Only the file stats moved, the root is the same as last time
+        Some(previous)
+            if previous
+                .children
+                .iter()
+                .map(|c| (&c.name, &c.hash, c.kind, &c.target))
+                .eq(children
+                    .iter()
+                    .map(|c| (&c.name, &c.hash, c.kind, &c.target))) =>
+        {
+            previous.root_hash
+        }
+        _ => cfg.algorithm.merkle_root(&children, cfg.schema_version)?,
+    };
 
+    // The patterns and symlink mode apply to the whole tree, they are
+    // recorded once at the root
+    let is_root = path == cfg.dataset_root;
+    let patterns = (is_root && !cfg.patterns.is_empty()).then(|| cfg.patterns.clone());
+    let symlinks = is_root.then_some(cfg.symlinks);
+    let dataset_root = cfg.recorded_root.clone().filter(|_| is_root);
     let node = Node {
+        schema_version: cfg.schema_version,
         name: rel_path_str(&cfg.dataset_root, path),
-        hash_method: cfg.method.to_string(),
+        hash_method: cfg.algorithm.name().to_string(),
+        non_cryptographic: cfg.non_cryptographic,
         root_hash,
         children,
         bytes_to_hash: cfg.bytes_to_hash,
+        chunking: cfg.chunking,
+        sampling: cfg.sampling,
+        patterns,
+        symlinks,
+        dataset_root,
     };
 
     if cfg.store {
-        let _ = store_node_to_disk(&node, &cfg.dataset_root, path, &cfg.rush_root);
+        store_node_to_disk(&node, path, cfg)?;
+    } else {
+        let rel = path.strip_prefix(&cfg.dataset_root)?;
+        manifest.nodes.insert(rel.to_path_buf(), node);
     }
 
     Ok(root_hash)
 }
 
-fn build<H>(
-    path: &Path,
-    method: &HashMethod,
-    bytes_to_hash: u64,
-    buffer_size: usize,
-    num_workers: usize,
-    store: bool,
-) -> Result<Digest>
-where
-    H: DigestCompatibleHasher,
-{
-    let mut file_names = Vec::new();
+/// Hash the dataset at `path` with the worker pool and build its Merkle tree.
+/// The nodes are stored under `.rush` (or `opts.manifest_dir`) or, when
+/// `opts.store` is false, returned in memory. With `opts.keep_going`, files that could not be hashed
+/// are left out of the tree and listed in the output. `path` can also be a
+/// tar or zip archive, its members are hashed as if it was extracted there.
+pub(crate) fn generic_build(path: &Path, opts: &BuildOptions) -> Result<BuildOutput> {
+    opts.check()?;
+    if path.is_file() {
+        return archive::build(path, opts);
+    }
+
+    // Get the rush root path, previous manifests are read from there
+    let rush_root = manifest_root(path, opts.manifest_dir.as_deref());
+    // A manifest stored elsewhere remembers which dataset it describes
+    let recorded_root = match opts.manifest_dir {
+        Some(_) => Some(fs::canonicalize(path)?),
+        None => None,
+    };
+    let previous = if opts.incremental && has_manifest(&rush_root) {
+        Some(open_manifest(&rush_root)?)
+    } else {
+        None
+    };
+    // Switching layout or format removes the previous files, every node is
+    // written again
+    let previous_stored =
+        manifest_file(&rush_root).is_none() && rush_root.join(opts.format.node_file()).is_file();
+    let cfg = BuildConfig {
+        dataset_root: path.to_path_buf(),
+        rush_root,
+        algorithm: opts.method.algorithm(),
+        non_cryptographic: !opts.method.is_cryptographic(),
+        bytes_to_hash: opts.bytes_to_hash,
+        chunking: opts.chunking,
+        sampling: opts.sampling,
+        // The single file is written once the whole tree is in memory
+        store: opts.store && opts.layout == ManifestLayout::Tree,
+        incremental: opts.incremental,
+        schema_version: opts.schema_version,
+        patterns: opts.patterns.clone(),
+        symlinks: opts.symlinks,
+        recorded_root,
+        previous,
+        previous_stored,
+        format: opts.format,
+    };
+    let root_ctx = DirContext {
+        rules: IgnoreRules::new(PathFilter::new(&opts.patterns)?),
+        ancestors: Vec::new(),
+    };
+
+    let mut files = Vec::new();
     // First DFS pass: Collect files
-    initialize(path, &mut file_names)?;
+    initialize(path, &root_ctx, &mut files, &cfg)?;
 
-    let nb_files = file_names.len();
+    // Only the files without a reusable hash go to the workers
+    let pending: Vec<usize> = (0..files.len())
+        .filter(|&i| files[i].cached.is_none())
+        .collect();
+    let nb_pending = pending.len();
     // Allocate atomic counter
     let next = AtomicUsize::new(0);
-    // Initialize result vector with zeros
-    let hashes: Vec<Digest> = vec![H::zero_digest(); nb_files];
-
-    // Spawn scope threads
-    thread::scope(|s| {
-        for _ in 0..num_workers {
-            s.spawn(|| -> Result<()> {
-                loop {
-                    // atomic counter
-                    let i = next.fetch_add(1, Relaxed);
-                    if i >= nb_files {
-                        break;
-                    }
-                    let hash = hash_file(&file_names[i], method, bytes_to_hash, buffer_size)?;
-                    // SAFETY: Each thread writes to a unique index, thanks to the atomic counter.
-                    // No races and we can safely deref the raw pointer.
-                    unsafe {
-                        let ptr = hashes.as_ptr().add(i);
-                        std::ptr::write(ptr as *mut Digest, hash);
+    // Initialize result vector with the cached hashes, zeros otherwise
+    let mut hashes: Vec<FileHash> = files
+        .iter()
+        .map(|f| {
+            f.cached
+                .clone()
+                .unwrap_or_else(|| FileHash::new(cfg.algorithm.zero_digest()))
+        })
+        .collect();
+
+    // Spawn scope threads, each worker returns the hashes it computed and
+    // the files it failed to hash
+    let abort = AtomicBool::new(false);
+    type Hashed = (Vec<(usize, FileHash)>, Vec<(usize, Error)>);
+    let results: Vec<Hashed> = thread::scope(|s| {
+        let handles: Vec<_> = (0..opts.num_workers)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut hashed = Vec::new();
+                    let mut errors = Vec::new();
+                    while !abort.load(Relaxed) {
+                        // atomic counter
+                        let next = next.fetch_add(1, Relaxed);
+                        if next >= nb_pending {
+                            break;
+                        }
+                        let i = pending[next];
+                        match hash_file_content(
+                            &files[i].path,
+                            &opts.method,
+                            opts.bytes_to_hash,
+                            opts.buffer_size,
+                            opts.chunking.as_ref(),
+                            opts.sampling.as_ref(),
+                        ) {
+                            Ok(hash) => hashed.push((i, hash)),
+                            Err(err) => {
+                                errors.push((i, err));
+                                if !opts.keep_going {
+                                    // No point hashing the rest, the build fails
+                                    abort.store(true, Relaxed);
+                                }
+                            }
+                        }
                     }
-                }
-                Ok(())
-            });
-        }
+                    (hashed, errors)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
     });
 
-    // assert_eq!(hashes.len(), file_names.len());
+    let mut errors = Vec::new();
+    for (hashed, failed) in results {
+        for (i, hash) in hashed {
+            hashes[i] = hash;
+        }
+        errors.extend(failed);
+    }
+
+    let mut failed: Vec<FailedFile> = errors
+        .into_iter()
+        .map(|(i, err)| {
+            files[i].failed = true;
+            FailedFile {
+                path: files[i].path.clone(),
+                error: err.to_string(),
+            }
+        })
+        .collect();
+    failed.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if !failed.is_empty() && !opts.keep_going {
+        return Err(Error::HashFailed(failed));
+    }
+
+    // assert_eq!(hashes.len(), files.len());
 
     // All the files are now hashed, we can build the merkle tree
-    // Get the rush root path
-    let rush_root = if store {
-        setup_build(path)?
-    } else {
-        path.into()
-    };
+    if opts.store {
+        setup_build(&cfg.rush_root, opts.layout, opts.format)?;
+        store_error_report(&failed, &cfg.rush_root)?;
+    }
     let mut file_index = 0;
-    let cfg = BuildConfig {
-        dataset_root: path.to_path_buf(),
-        rush_root,
-        method: method.as_str(),
-        bytes_to_hash,
-        store,
-    };
-    let root = build_merkle_tree::<H>(path, &hashes, &mut file_index, &cfg)?;
+    let mut manifest = MemoryManifest::default();
+    let root = build_merkle_tree(
+        path,
+        &root_ctx,
+        &files,
+        &hashes,
+        &mut file_index,
+        &cfg,
+        &mut manifest,
+    )?;
+    if opts.store && opts.layout == ManifestLayout::File {
+        let file = cfg.rush_root.join(opts.format.manifest_file());
+        FileManifest::write(&manifest, &file, opts.format)?;
+        manifest = MemoryManifest::default();
+    }
 
-    Ok(root)
+    Ok(BuildOutput {
+        root,
+        manifest,
+        failed,
+    })
 }
 
-fn generic_build(
-    path: &Path,
-    method: &HashMethod,
-    bytes_to_hash: u64,
-    buffer_size: usize,
-    num_workers: usize,
-) -> Result<Digest> {
-    let hash_root = match method {
-        HashMethod::Md5 => {
-            build::<Md5Algorithm>(path, method, bytes_to_hash, buffer_size, num_workers, true)?
-        }
-        HashMethod::Blake3 => {
-            build::<Blake3Algorithm>(path, method, bytes_to_hash, buffer_size, num_workers, true)?
-        }
-        HashMethod::Sha256 => {
-            build::<Sha256Algorithm>(path, method, bytes_to_hash, buffer_size, num_workers, true)?
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::DatasetHasher;
+    use crate::utils::write_dataset;
+
+    const LAYOUT: &[(&str, &str)] = &[("a/b/one.txt", "one"), ("c/two.txt", "two")];
+
+    fn stored_nodes(dir: &Path) -> usize {
+        MemoryManifest::load(&*open_manifest(&dir.join(".rush")).unwrap())
+            .unwrap()
+            .nodes
+            .len()
+    }
+
+    #[test]
+    fn incremental_single_file_keeps_unchanged_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dataset(dir.path(), LAYOUT);
+        let hasher = || DatasetHasher::new(dir.path()).manifest_layout(ManifestLayout::File);
+        let first = hasher().build().unwrap().root;
+        assert_eq!(stored_nodes(dir.path()), 4);
+
+        fs::write(dir.path().join("c/two.txt"), "changed").unwrap();
+        let second = hasher().incremental(true).build().unwrap().root;
+        assert_ne!(first, second);
+        assert_eq!(stored_nodes(dir.path()), 4);
+    }
+
+    #[test]
+    fn incremental_in_memory_keeps_unchanged_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dataset(dir.path(), LAYOUT);
+        DatasetHasher::new(dir.path()).build().unwrap();
+        let output = DatasetHasher::new(dir.path())
+            .store(false)
+            .incremental(true)
+            .build()
+            .unwrap();
+        assert_eq!(output.manifest.nodes.len(), 4);
+    }
+
+    #[test]
+    fn node_write_errors_fail_the_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        let manifests = dir.path().join("manifests");
+        write_dataset(&data, LAYOUT);
+        // The node of `a` cannot be written where a file is
+        write_dataset(&manifests, &[("a", "not a folder")]);
+        let result = DatasetHasher::new(&data).manifest_dir(&manifests).build();
+        assert!(matches!(result, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn switching_layout_writes_every_node() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dataset(dir.path(), LAYOUT);
+        let rush_root = dir.path().join(".rush");
+        DatasetHasher::new(dir.path())
+            .manifest_layout(ManifestLayout::File)
+            .build()
+            .unwrap();
+        DatasetHasher::new(dir.path())
+            .incremental(true)
+            .build()
+            .unwrap();
+        for rel in ["", "a", "a/b", "c"] {
+            assert!(
+                rush_root
+                    .join(rel)
+                    .join(ManifestFormat::Json.node_file())
+                    .is_file()
+            );
         }
-    };
 
-    Ok(hash_root)
+        DatasetHasher::new(dir.path())
+            .manifest_format(ManifestFormat::Cbor)
+            .incremental(true)
+            .build()
+            .unwrap();
+        for rel in ["", "a", "a/b", "c"] {
+            assert!(
+                rush_root
+                    .join(rel)
+                    .join(ManifestFormat::Cbor.node_file())
+                    .is_file()
+            );
+        }
+        assert_eq!(stored_nodes(dir.path()), 4);
+    }
 }
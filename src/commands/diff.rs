@@ -1,65 +1,464 @@
-use crate::hashers::utils::Digest;
-use crate::{utils::Node, utils::node_from_file};
-use anyhow::{Result, bail};
-use std::collections::BTreeMap;
-use std::fmt::Write;
+use crate::archive;
+use crate::commands::build::generic_build;
+use crate::error::{Error, Result};
+use crate::filter::{PathFilter, Patterns};
+use crate::format::ManifestFormat;
+use crate::hashers::chunking::{Chunk, Chunking, hash_content};
+use crate::hashers::merkle::SCHEMA_V2;
+use crate::hashers::sampling::Sampling;
+use crate::hashers::utils::{Digest, HashMethod};
+use crate::utils::{
+    BuildOptions, EntryKind, Leaf, ManifestReader, Node, has_manifest, manifest_root, open_manifest,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{self, Write};
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 
-pub(crate) fn invoke(lhs: &Path, rhs: &Path) -> Result<()> {
-    if let Some(d) = diff(lhs, rhs)? {
-        // print in a simple, deterministic order
-        for k in d.added {
-            println!("Only in {}: {}", rhs.display(), k);
+/// Version of the `--format json|ndjson` output. Bumped whenever a field is
+/// renamed or removed, adding fields keeps the version.
+pub const DIFF_FORMAT_VERSION: u32 = 1;
+
+#[derive(Default, Clone, Copy, Debug, ValueEnum)]
+pub enum DiffFormat {
+    /// One line per difference, like GNU diff
+    #[default]
+    Text,
+    /// A single JSON document
+    Json,
+    /// One JSON object per difference
+    Ndjson,
+}
+
+/// User facing options of `rush diff`
+pub struct DiffOptions {
+    pub patterns: Patterns,
+    pub format: DiffFormat,
+    /// Print nothing, only return whether the datasets differ
+    pub quiet: bool,
+    /// Pair removed and added paths with the same digest into moves
+    pub renames: bool,
+    /// Used to hash a directory without manifest. Taken from the other side
+    /// when it has one, the build defaults otherwise.
+    pub method: Option<HashMethod>,
+    pub bytes_to_hash: Option<u64>,
+    pub num_workers: usize,
+    /// Manifests stored outside their dataset, matched with the operands
+    /// through the dataset path they record
+    pub manifest_dirs: Vec<PathBuf>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            patterns: Patterns::default(),
+            format: DiffFormat::default(),
+            quiet: false,
+            renames: true,
+            method: None,
+            bytes_to_hash: None,
+            num_workers: 4,
+            manifest_dirs: Vec::new(),
         }
-        for k in d.removed {
-            println!("Only in {}: {}", lhs.display(), k);
+    }
+}
+
+/// Print the differences between two datasets, returns whether they differ
+pub fn invoke(lhs: &Path, rhs: &Path, opts: &DiffOptions) -> Result<bool> {
+    let (lhs_manifest, rhs_manifest) = open_operands(lhs, rhs, opts)?;
+    if !opts.quiet {
+        warn_on_different_patterns(&*lhs_manifest, &*rhs_manifest)?;
+    }
+    let filter = PathFilter::new(&opts.patterns)?;
+    let d = diff_manifests(&*lhs_manifest, &*rhs_manifest, &filter, opts.renames)?;
+    let differs = d.is_some();
+    if opts.quiet {
+        return Ok(differs);
+    }
+    match opts.format {
+        DiffFormat::Text => print_text(lhs, rhs, d),
+        DiffFormat::Json => {
+            let d = d.unwrap_or_default();
+            let report = DiffReport {
+                version: DIFF_FORMAT_VERSION,
+                lhs,
+                rhs,
+                identical: d.is_empty(),
+                diff: &d,
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
         }
-        for k in d.changed {
-            println!("Present in both but content differs: {}", k);
+        DiffFormat::Ndjson => {
+            let d = d.unwrap_or_default();
+            let changes = [
+                ("added", &d.added),
+                ("removed", &d.removed),
+                ("changed", &d.changed),
+                ("retargeted", &d.retargeted),
+            ];
+            for (change, entries) in changes {
+                for entry in entries {
+                    let line = DiffLine {
+                        version: DIFF_FORMAT_VERSION,
+                        change,
+                        entry,
+                    };
+                    println!("{}", serde_json::to_string(&line)?);
+                }
+            }
+            for entry in &d.moved {
+                let line = DiffLine {
+                    version: DIFF_FORMAT_VERSION,
+                    change: "moved",
+                    entry,
+                };
+                println!("{}", serde_json::to_string(&line)?);
+            }
         }
     }
+    Ok(differs)
+}
+
+fn print_text(lhs: &Path, rhs: &Path, d: Option<Diff>) {
     // If None => identical => print nothing (like GNU diff)
-    Ok(())
+    let Some(d) = d else {
+        return;
+    };
+    // print in a simple, deterministic order
+    for k in d.added {
+        println!("Only in {}: {}", rhs.display(), k.path);
+    }
+    for k in d.removed {
+        println!("Only in {}: {}", lhs.display(), k.path);
+    }
+    for k in d.moved {
+        println!("Moved: {} -> {}", k.from, k.to);
+    }
+    for k in d.changed {
+        match &k.ranges {
+            Some(ranges) if !ranges.is_empty() => {
+                let ranges: Vec<String> = ranges.iter().map(ByteRange::to_string).collect();
+                println!(
+                    "Present in both but content differs: {} (bytes {})",
+                    k.path,
+                    ranges.join(", ")
+                );
+            }
+            _ => println!("Present in both but content differs: {}", k.path),
+        }
+    }
+    for k in d.retargeted {
+        println!("Symlink target differs: {}", k.path);
+    }
+}
+
+/// `--format json` document
+#[derive(Serialize)]
+struct DiffReport<'a> {
+    version: u32,
+    lhs: &'a Path,
+    rhs: &'a Path,
+    identical: bool,
+    #[serde(flatten)]
+    diff: &'a Diff,
+}
+
+/// `--format ndjson` line
+#[derive(Serialize)]
+struct DiffLine<'a, T> {
+    version: u32,
+    /// added, removed, changed, retargeted or moved
+    change: &'static str,
+    #[serde(flatten)]
+    entry: &'a T,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 pub struct Diff {
-    pub added: Vec<String>,   // present only in rhs
-    pub removed: Vec<String>, // present only in lhs
-    pub changed: Vec<String>, // present in both but different
+    pub added: Vec<DiffEntry>,      // present only in rhs
+    pub removed: Vec<DiffEntry>,    // present only in lhs
+    pub changed: Vec<DiffEntry>,    // present in both but different
+    pub retargeted: Vec<DiffEntry>, // symlink present in both with another target
+    pub moved: Vec<DiffMove>,       // same digest at another path
 }
 
-impl Diff {
-    pub fn is_empty(&self) -> bool {
-        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+/// A path that differs between the two trees
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffEntry {
+    /// Relative to the dataset roots, `/` separated
+    pub path: String,
+    /// Type on the rhs side, or on the lhs side for a removed path
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    /// Digest in the lhs tree, none when added
+    pub old: Option<Digest>,
+    /// Digest in the rhs tree, none when removed
+    pub new: Option<Digest>,
+    /// Symlink targets, none for other entries
+    pub old_target: Option<String>,
+    pub new_target: Option<String>,
+    /// Byte ranges of the new file whose chunks are not in the old one, only
+    /// for changed files of chunked builds
+    pub ranges: Option<Vec<ByteRange>>,
+}
+
+/// Half-open range of bytes, `end` excluded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
     }
 }
 
-fn node_from_path(path: &Path) -> Result<Node> {
-    let node_path = path.join("merkle.json");
-    let node = node_from_file(&node_path)?;
-    Ok(node)
+/// Ranges covered by the chunks of `new` that are nowhere in `old`, adjacent
+/// chunks merged
+fn changed_ranges(old: &[Chunk], new: &[Chunk]) -> Vec<ByteRange> {
+    let known: HashSet<&Digest> = old.iter().map(|c| &c.hash).collect();
+    let mut ranges: Vec<ByteRange> = Vec::new();
+    for chunk in new.iter().filter(|c| !known.contains(&c.hash)) {
+        let end = chunk.offset + chunk.length;
+        match ranges.last_mut() {
+            Some(last) if last.end == chunk.offset => last.end = end,
+            _ => ranges.push(ByteRange {
+                start: chunk.offset,
+                end,
+            }),
+        }
+    }
+    ranges
 }
 
-fn map_children(node: &Node) -> BTreeMap<&str, &Digest> {
+impl DiffEntry {
+    fn new(path: String, kind: EntryKind, old: Option<&Leaf>, new: Option<&Leaf>) -> Self {
+        DiffEntry {
+            path,
+            kind,
+            old: old.map(|l| l.hash),
+            new: new.map(|l| l.hash),
+            old_target: old.and_then(|l| l.target.clone()),
+            new_target: new.and_then(|l| l.target.clone()),
+            ranges: match (
+                old.and_then(|l| l.chunks.as_ref()),
+                new.and_then(|l| l.chunks.as_ref()),
+            ) {
+                (Some(old), Some(new)) => Some(changed_ranges(old, new)),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// A path of the lhs tree found at another path of the rhs tree
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffMove {
+    pub from: String,
+    pub to: String,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    /// Digest on both sides, the root hash for a directory
+    pub digest: Digest,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.retargeted.is_empty()
+            && self.moved.is_empty()
+    }
+}
+
+fn map_children(node: &Node) -> BTreeMap<&str, &Leaf> {
     let mut children = BTreeMap::new();
 
     for c in &node.children {
-        children.insert(c.name.as_str(), &c.hash);
+        children.insert(c.name.as_str(), c);
     }
 
     children
 }
 
+/// Both trees may have been built with different `--exclude`/`--include`
+/// patterns, in which case some differences only come from the rules
+fn warn_on_different_patterns(lhs: &dyn ManifestReader, rhs: &dyn ManifestReader) -> Result<()> {
+    let lhs_root = lhs.node(Path::new(""))?;
+    let rhs_root = rhs.node(Path::new(""))?;
+    if lhs_root.patterns != rhs_root.patterns {
+        eprintln!(
+            "warning: the datasets were built with different patterns: {} vs {}",
+            serde_json::to_string(&lhs_root.patterns.clone().unwrap_or_default())?,
+            serde_json::to_string(&rhs_root.patterns.clone().unwrap_or_default())?
+        );
+    }
+    Ok(())
+}
+
+/// A diff operand, before hashing the ones without a manifest
+enum Operand {
+    Manifest(Box<dyn ManifestReader>),
+    Live(PathBuf),
+}
+
+impl Operand {
+    /// A manifest file, a dataset or an archive with its manifest in
+    /// `manifest_dir` or in `.rush`, a manifest folder, or a plain directory
+    /// or archive
+    fn locate(path: &Path, manifest_dir: Option<PathBuf>) -> Result<Self> {
+        let rush_root = manifest_root(path, None);
+        let is_archive = archive::is_archive(path)?;
+        if path.is_file() && !is_archive {
+            Ok(Operand::Manifest(open_manifest(path)?))
+        } else if let Some(dir) = manifest_dir {
+            Ok(Operand::Manifest(open_manifest(&dir)?))
+        } else if has_manifest(&rush_root) {
+            Ok(Operand::Manifest(open_manifest(&rush_root)?))
+        } else if is_manifest_dir(path)? {
+            Ok(Operand::Manifest(open_manifest(path)?))
+        } else if path.is_dir() || is_archive {
+            Ok(Operand::Live(path.to_path_buf()))
+        } else {
+            Err(Error::NotADirectory(path.to_path_buf()))
+        }
+    }
+
+    fn root(&self) -> Option<Node> {
+        match self {
+            Operand::Manifest(m) => m.node(Path::new("")).ok().map(Cow::into_owned),
+            Operand::Live(_) => None,
+        }
+    }
+
+    /// Hash a plain directory in memory, with the settings of `reference`
+    /// when the other side has a manifest
+    fn open(self, reference: Option<&Node>, opts: &DiffOptions) -> Result<Box<dyn ManifestReader>> {
+        let path = match self {
+            Operand::Manifest(m) => return Ok(m),
+            Operand::Live(path) => path,
+        };
+        let method = match (&opts.method, reference) {
+            (Some(method), _) => method.clone(),
+            (None, Some(root)) => HashMethod::from_name(&root.hash_method)?,
+            (None, None) => HashMethod::default(),
+        };
+        let build_opts = BuildOptions {
+            method,
+            bytes_to_hash: opts
+                .bytes_to_hash
+                .or(reference.map(|r| r.bytes_to_hash))
+                .unwrap_or(0),
+            chunking: reference.and_then(|r| r.chunking),
+            sampling: reference.and_then(|r| r.sampling),
+            num_workers: opts.num_workers,
+            store: false,
+            patterns: match reference {
+                Some(root) => root.patterns.clone().unwrap_or_default(),
+                None => opts.patterns.clone(),
+            },
+            symlinks: reference.and_then(|r| r.symlinks).unwrap_or_default(),
+            schema_version: reference.map_or(SCHEMA_V2, |r| r.schema_version),
+            ..BuildOptions::default()
+        };
+        Ok(Box::new(generic_build(&path, &build_opts)?.manifest))
+    }
+}
+
+/// Whether `dir` is a manifest folder rather than a dataset that happens to
+/// hold a file named like a manifest: it only holds manifest files and the
+/// folders of the subdirectory nodes
+fn is_manifest_dir(dir: &Path) -> Result<bool> {
+    if !dir.is_dir() || !has_manifest(dir) {
+        return Ok(false);
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let known = ManifestFormat::ALL
+            .iter()
+            .any(|f| name == f.node_file() || name == f.manifest_file())
+            || name == "errors.json";
+        if !known && !entry.file_type()?.is_dir() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+type ManifestPair = (Box<dyn ManifestReader>, Box<dyn ManifestReader>);
+
+/// The manifest folder of `manifest_dirs` recording `dataset` as its root
+fn find_manifest_dir(dataset: &Path, manifest_dirs: &[PathBuf]) -> Result<Option<PathBuf>> {
+    let Ok(dataset) = fs::canonicalize(dataset) else {
+        return Ok(None);
+    };
+    for dir in manifest_dirs {
+        let manifest = open_manifest(dir)?;
+        let root = manifest.node(Path::new(""))?;
+        if root.dataset_root.as_ref() == Some(&dataset) {
+            return Ok(Some(dir.clone()));
+        }
+    }
+    Ok(None)
+}
+
+fn open_operands(path_l: &Path, path_r: &Path, opts: &DiffOptions) -> Result<ManifestPair> {
+    let lhs_dir = find_manifest_dir(path_l, &opts.manifest_dirs)?;
+    let rhs_dir = find_manifest_dir(path_r, &opts.manifest_dirs)?;
+    for dir in &opts.manifest_dirs {
+        if !opts.quiet && lhs_dir.as_ref() != Some(dir) && rhs_dir.as_ref() != Some(dir) {
+            eprintln!(
+                "warning: {} is the manifest of neither dataset, ignored",
+                dir.display()
+            );
+        }
+    }
+    let lhs = Operand::locate(path_l, lhs_dir)?;
+    let rhs = Operand::locate(path_r, rhs_dir)?;
+    let reference = lhs.root().or_else(|| rhs.root());
+    Ok((
+        lhs.open(reference.as_ref(), opts)?,
+        rhs.open(reference.as_ref(), opts)?,
+    ))
+}
+
+/// Compare two datasets. Each side is a manifest file, a manifest folder, a
+/// dataset with a `.rush` folder, or a plain directory or archive hashed in
+/// memory.
 pub fn diff(path_l: &Path, path_r: &Path) -> Result<Option<Diff>> {
-    // Prepare paths by redirecting to hidden Merkle Tree folder
-    let path_l = path_l.join(".rush");
-    let path_r = path_r.join(".rush");
+    diff_with(path_l, path_r, &DiffOptions::default())
+}
+
+/// Same as `diff` with the patterns, renames and hashing settings of `opts`
+pub fn diff_with(path_l: &Path, path_r: &Path, opts: &DiffOptions) -> Result<Option<Diff>> {
+    let (lhs, rhs) = open_operands(path_l, path_r, opts)?;
+    let filter = PathFilter::new(&opts.patterns)?;
+    diff_manifests(&*lhs, &*rhs, &filter, opts.renames)
+}
+
+/// Compare two manifests, leaving out the paths excluded by `filter`.
+/// Without `renames`, moved paths are reported as removed and added.
+pub fn diff_manifests(
+    lhs: &dyn ManifestReader,
+    rhs: &dyn ManifestReader,
+    filter: &PathFilter,
+    renames: bool,
+) -> Result<Option<Diff>> {
     let mut out = Diff::default();
 
     // Recursive call
-    diff_rec(&path_l, &path_r, Path::new(""), &mut out)?;
+    diff_rec(lhs, rhs, Path::new(""), filter, &mut out)?;
+    if renames {
+        detect_moves(lhs, rhs, filter, &mut out)?;
+    }
 
     if out.is_empty() {
         Ok(None)
@@ -68,32 +467,61 @@ pub fn diff(path_l: &Path, path_r: &Path) -> Result<Option<Diff>> {
     }
 }
 
-fn diff_rec(lhs_path: &Path, rhs_path: &Path, rel: &Path, out: &mut Diff) -> Result<()> {
-    let lhs_node = node_from_path(lhs_path)?;
-    let rhs_node = node_from_path(rhs_path)?;
+pub(crate) fn diff_rec(
+    lhs: &dyn ManifestReader,
+    rhs: &dyn ManifestReader,
+    rel: &Path,
+    filter: &PathFilter,
+    out: &mut Diff,
+) -> Result<()> {
+    let lhs_node = lhs.node(rel)?;
+    let rhs_node = rhs.node(rel)?;
 
     // Sanity checks. Fails if meta data is not equal
     // 1. We check that we use the same hashing method
     if lhs_node.hash_method != rhs_node.hash_method {
-        bail!(
-            "Hash methods differ: {} vs {}",
-            lhs_node.hash_method,
-            rhs_node.hash_method
-        )
+        return Err(Error::HashMethodMismatch(
+            lhs_node.hash_method.clone(),
+            rhs_node.hash_method.clone(),
+        ));
     }
 
     // 2. We check that we use the same number of bytes to hash
     if lhs_node.bytes_to_hash != rhs_node.bytes_to_hash {
-        bail!(
-            "Bytes to hash differ at {}: {} vs {}",
-            rel.display(),
-            lhs_node.bytes_to_hash,
-            rhs_node.bytes_to_hash
-        )
+        return Err(Error::BytesToHashMismatch {
+            path: rel.to_path_buf(),
+            lhs: lhs_node.bytes_to_hash,
+            rhs: rhs_node.bytes_to_hash,
+        });
+    }
+
+    // 3. Chunked leaf hashes are chunk roots, only comparable with the same
+    // chunk sizes
+    if lhs_node.chunking != rhs_node.chunking {
+        let describe = |c: Option<Chunking>| c.map_or("no chunking".to_string(), |c| c.to_string());
+        return Err(Error::ChunkingMismatch {
+            path: rel.to_path_buf(),
+            lhs: describe(lhs_node.chunking),
+            rhs: describe(rhs_node.chunking),
+        });
+    }
+
+    // 4. Sampled digests only see some windows of the files, the same ones
+    // on both sides
+    if lhs_node.sampling != rhs_node.sampling {
+        let describe = |s: Option<Sampling>| s.map_or("no sampling".to_string(), |s| s.to_string());
+        return Err(Error::SamplingMismatch {
+            path: rel.to_path_buf(),
+            lhs: describe(lhs_node.sampling),
+            rhs: describe(rhs_node.sampling),
+        });
     }
 
-    // We can now make the comparisons
-    if lhs_node.root_hash == rhs_node.root_hash {
+    // We can now make the comparisons. Roots of different manifest versions
+    // are not comparable, the children digests still are.
+    if lhs_node.schema_version == rhs_node.schema_version
+        && lhs_node.root_hash == rhs_node.root_hash
+    {
         return Ok(());
     }
 
@@ -101,39 +529,261 @@ fn diff_rec(lhs_path: &Path, rhs_path: &Path, rel: &Path, out: &mut Diff) -> Res
     let right_children = map_children(&rhs_node);
 
     // Removed (only in left)
-    for name in left_children.keys() {
-        if !right_children.contains_key(name) {
-            out.removed.push(path_join(rel, name));
+    for (name, lleaf) in &left_children {
+        let sub = rel.join(name);
+        let kind = entry_kind(lleaf, lhs, &sub);
+        if !right_children.contains_key(name) && !filter.is_excluded(&sub, kind == EntryKind::Dir) {
+            out.removed.push(DiffEntry::new(
+                path_join(rel, name),
+                kind,
+                Some(lleaf),
+                None,
+            ));
         }
     }
     // Added (only in right)
-    for name in right_children.keys() {
-        if !left_children.contains_key(name) {
-            out.added.push(path_join(rel, name));
+    for (name, rleaf) in &right_children {
+        let sub = rel.join(name);
+        let kind = entry_kind(rleaf, rhs, &sub);
+        if !left_children.contains_key(name) && !filter.is_excluded(&sub, kind == EntryKind::Dir) {
+            out.added.push(DiffEntry::new(
+                path_join(rel, name),
+                kind,
+                None,
+                Some(rleaf),
+            ));
         }
     }
     // Changed (present in both but different)
-    for (name, lhash) in &left_children {
-        if let Some(rhash) = right_children.get(name) {
-            if lhash == rhash {
+    for (name, lleaf) in &left_children {
+        if let Some(rleaf) = right_children.get(name) {
+            let sub = rel.join(name);
+            if lleaf.target != rleaf.target {
+                let kind = entry_kind(rleaf, rhs, &sub);
+                out.retargeted.push(DiffEntry::new(
+                    path_join(rel, name),
+                    kind,
+                    Some(lleaf),
+                    Some(rleaf),
+                ));
+                // The hash of a link that is not followed is its target
+                if lleaf.kind == Some(EntryKind::Symlink) && rleaf.kind == Some(EntryKind::Symlink)
+                {
+                    continue;
+                }
+            }
+            if lleaf.hash == rleaf.hash {
                 continue;
             }
             // children are different
-            let lsub = lhs_path.join(name);
-            let rsub = rhs_path.join(name);
+            let lkind = entry_kind(lleaf, lhs, &sub);
+            let kind = entry_kind(rleaf, rhs, &sub);
+            if filter.is_excluded(&sub, lkind == EntryKind::Dir || kind == EntryKind::Dir) {
+                continue;
+            }
 
             // need to check if the children is a dir or not
-            if is_rush_dir(&lsub) && is_rush_dir(&rsub) {
-                diff_rec(&lsub, &rsub, &rel.join(name), out)?;
+            if lkind == EntryKind::Dir && kind == EntryKind::Dir {
+                diff_rec(lhs, rhs, &sub, filter, out)?;
             } else {
                 // treat as a leaf change
-                out.changed.push(path_join(rel, name));
+                out.changed.push(DiffEntry::new(
+                    path_join(rel, name),
+                    kind,
+                    Some(lleaf),
+                    Some(rleaf),
+                ));
             }
         }
     }
     Ok(())
 }
 
+/// A removed or added path that may be one side of a move
+struct MoveCandidate {
+    path: String,
+    kind: EntryKind,
+    digest: Digest,
+    /// Index in `Diff::removed` or `Diff::added`, none for a path inside a
+    /// removed or added directory
+    entry: Option<usize>,
+}
+
+/// Post-pass pairing removed and added paths with the same type and digest.
+/// Whole entries are paired first, which catches directory moves through
+/// their root hash. The files inside the directories left over are paired
+/// next, so a file moved into a new directory is found too.
+fn detect_moves(
+    lhs: &dyn ManifestReader,
+    rhs: &dyn ManifestReader,
+    filter: &PathFilter,
+    out: &mut Diff,
+) -> Result<()> {
+    let entries = |list: &[DiffEntry], old: bool| {
+        list.iter()
+            .enumerate()
+            .filter_map(|(i, e)| {
+                Some(MoveCandidate {
+                    path: e.path.clone(),
+                    kind: e.kind,
+                    digest: if old { e.old? } else { e.new? },
+                    entry: Some(i),
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let empty = empty_digests(&*lhs.node(Path::new(""))?)?;
+    let pairs = pair_candidates(
+        entries(&out.removed, true),
+        entries(&out.added, false),
+        &empty,
+    );
+    apply_moves(pairs, out);
+
+    let mut removed = Vec::new();
+    for (i, e) in out.removed.iter().enumerate() {
+        if e.kind == EntryKind::Dir {
+            collect_files(lhs, Path::new(&e.path), filter, &mut removed)?;
+        } else if let Some(digest) = e.old {
+            removed.push(MoveCandidate {
+                path: e.path.clone(),
+                kind: e.kind,
+                digest,
+                entry: Some(i),
+            });
+        }
+    }
+    let mut added = Vec::new();
+    for (i, e) in out.added.iter().enumerate() {
+        if e.kind == EntryKind::Dir {
+            collect_files(rhs, Path::new(&e.path), filter, &mut added)?;
+        } else if let Some(digest) = e.new {
+            added.push(MoveCandidate {
+                path: e.path.clone(),
+                kind: e.kind,
+                digest,
+                entry: Some(i),
+            });
+        }
+    }
+    apply_moves(pair_candidates(removed, added, &empty), out);
+
+    out.moved.sort_by(|a, b| a.from.cmp(&b.from));
+    Ok(())
+}
+
+/// Digests of an empty file and of an empty directory in the manifest of
+/// `root`. Every empty entry has them, they do not tell where one moved.
+fn empty_digests(root: &Node) -> Result<[(EntryKind, Digest); 2]> {
+    let method = HashMethod::from_name(&root.hash_method)?;
+    let file = hash_content(
+        io::empty(),
+        0,
+        &method,
+        root.bytes_to_hash,
+        8192,
+        root.chunking.as_ref(),
+        root.sampling.as_ref(),
+    )?;
+    Ok([
+        (EntryKind::File, file.digest),
+        (EntryKind::Dir, method.algorithm().empty_root()),
+    ])
+}
+
+/// Pair each removed candidate with the first added one of same type and
+/// digest, both lists being in path order. Empty entries are left out.
+fn pair_candidates(
+    removed: Vec<MoveCandidate>,
+    added: Vec<MoveCandidate>,
+    empty: &[(EntryKind, Digest)],
+) -> Vec<(MoveCandidate, MoveCandidate)> {
+    let mut by_digest: HashMap<(EntryKind, Digest), VecDeque<MoveCandidate>> = HashMap::new();
+    for c in added
+        .into_iter()
+        .filter(|c| !empty.contains(&(c.kind, c.digest)))
+    {
+        by_digest
+            .entry((c.kind, c.digest))
+            .or_default()
+            .push_back(c);
+    }
+    removed
+        .into_iter()
+        .filter_map(|r| {
+            let a = by_digest.get_mut(&(r.kind, r.digest))?.pop_front()?;
+            Some((r, a))
+        })
+        .collect()
+}
+
+/// Record the moves and drop the paired entries from the removed and added
+/// lists. A directory holding a moved file stays removed or added.
+fn apply_moves(pairs: Vec<(MoveCandidate, MoveCandidate)>, out: &mut Diff) {
+    let mut removed = HashSet::new();
+    let mut added = HashSet::new();
+    for (r, a) in pairs {
+        removed.extend(r.entry);
+        added.extend(a.entry);
+        out.moved.push(DiffMove {
+            from: r.path,
+            to: a.path,
+            kind: r.kind,
+            digest: r.digest,
+        });
+    }
+    let mut i = 0;
+    out.removed.retain(|_| {
+        i += 1;
+        !removed.contains(&(i - 1))
+    });
+    let mut i = 0;
+    out.added.retain(|_| {
+        i += 1;
+        !added.contains(&(i - 1))
+    });
+}
+
+/// Every file and symlink below `rel`, in path order
+fn collect_files(
+    reader: &dyn ManifestReader,
+    rel: &Path,
+    filter: &PathFilter,
+    out: &mut Vec<MoveCandidate>,
+) -> Result<()> {
+    let node = reader.node(rel)?;
+    for c in &node.children {
+        let sub = rel.join(&c.name);
+        let kind = entry_kind(c, reader, &sub);
+        if filter.is_excluded(&sub, kind == EntryKind::Dir) {
+            continue;
+        }
+        if kind == EntryKind::Dir {
+            collect_files(reader, &sub, filter, out)?;
+        } else {
+            out.push(MoveCandidate {
+                path: path_join(rel, &c.name),
+                kind,
+                digest: c.hash,
+                entry: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Type of a child as recorded in its leaf. Version 1 manifests do not
+/// record it, a child is a directory when it has its own node.
+fn entry_kind(leaf: &Leaf, reader: &dyn ManifestReader, sub: &Path) -> EntryKind {
+    leaf.kind.unwrap_or_else(|| {
+        if reader.is_dir(sub) {
+            EntryKind::Dir
+        } else {
+            EntryKind::File
+        }
+    })
+}
+
 fn path_join(rel: &Path, name: &str) -> String {
     if rel.as_os_str().is_empty() {
         // root level children
@@ -145,8 +795,103 @@ fn path_join(rel: &Path, name: &str) -> String {
     }
 }
 
-fn is_rush_dir(path: &Path) -> bool {
-    fs::metadata(path.join("merkle.json"))
-        .map(|m| m.is_file())
-        .unwrap_or(false)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::write_dataset;
+
+    /// Diff of two datasets written from their layouts
+    fn diff_layouts(lhs: &[(&str, &str)], rhs: &[(&str, &str)], renames: bool) -> Diff {
+        let dir = tempfile::tempdir().unwrap();
+        let (l, r) = (dir.path().join("lhs"), dir.path().join("rhs"));
+        write_dataset(&l, lhs);
+        write_dataset(&r, rhs);
+        let opts = DiffOptions {
+            renames,
+            ..DiffOptions::default()
+        };
+        diff_with(&l, &r, &opts).unwrap().unwrap_or_default()
+    }
+
+    fn paths(entries: &[DiffEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.path.as_str()).collect()
+    }
+
+    fn moves(d: &Diff) -> Vec<(&str, &str, EntryKind)> {
+        d.moved
+            .iter()
+            .map(|m| (m.from.as_str(), m.to.as_str(), m.kind))
+            .collect()
+    }
+
+    #[test]
+    fn moved_file_is_paired() {
+        let d = diff_layouts(
+            &[("a.txt", "content"), ("kept.txt", "kept")],
+            &[("b.txt", "content"), ("kept.txt", "kept")],
+            true,
+        );
+        assert_eq!(moves(&d), [("a.txt", "b.txt", EntryKind::File)]);
+        assert!(d.added.is_empty() && d.removed.is_empty() && d.changed.is_empty());
+    }
+
+    #[test]
+    fn moved_directory_is_paired_by_its_root() {
+        let d = diff_layouts(
+            &[("src/x.txt", "x"), ("src/sub/y.txt", "y")],
+            &[("dst/x.txt", "x"), ("dst/sub/y.txt", "y")],
+            true,
+        );
+        // The files inside are not reported on their own
+        assert_eq!(moves(&d), [("src", "dst", EntryKind::Dir)]);
+        assert!(d.added.is_empty() && d.removed.is_empty());
+    }
+
+    #[test]
+    fn file_moved_into_a_new_directory_is_paired() {
+        let d = diff_layouts(
+            &[("a.txt", "content"), ("old/z.txt", "z")],
+            &[("new/a.txt", "content"), ("new/b.txt", "b")],
+            true,
+        );
+        assert_eq!(moves(&d), [("a.txt", "new/a.txt", EntryKind::File)]);
+        // The directories themselves did not move
+        assert_eq!(paths(&d.removed), ["old"]);
+        assert_eq!(paths(&d.added), ["new"]);
+    }
+
+    #[test]
+    fn unpaired_entries_are_kept() {
+        let d = diff_layouts(
+            &[("a", "1"), ("b", "2"), ("c", "3"), ("e", "5")],
+            &[("a2", "1"), ("c", "3"), ("d", "4"), ("e2", "5")],
+            true,
+        );
+        assert_eq!(
+            moves(&d),
+            [("a", "a2", EntryKind::File), ("e", "e2", EntryKind::File)]
+        );
+        assert_eq!(paths(&d.removed), ["b"]);
+        assert_eq!(paths(&d.added), ["d"]);
+    }
+
+    #[test]
+    fn empty_entries_are_not_paired() {
+        let d = diff_layouts(
+            &[("empty.txt", ""), ("folder/", "")],
+            &[("other.txt", ""), ("elsewhere/", "")],
+            true,
+        );
+        assert!(d.moved.is_empty());
+        assert_eq!(paths(&d.removed), ["empty.txt", "folder"]);
+        assert_eq!(paths(&d.added), ["elsewhere", "other.txt"]);
+    }
+
+    #[test]
+    fn moves_are_not_detected_without_renames() {
+        let d = diff_layouts(&[("a.txt", "content")], &[("b.txt", "content")], false);
+        assert!(d.moved.is_empty());
+        assert_eq!(paths(&d.removed), ["a.txt"]);
+        assert_eq!(paths(&d.added), ["b.txt"]);
+    }
 }
@@ -1,10 +1,9 @@
 use std::path::Path;
 
-use anyhow::{Ok, Result};
-
+use crate::error::{Error, Result};
 use crate::hashers::utils::{HashMethod, hash_file};
 
-pub(crate) fn invoke(
+pub fn invoke(
     path: &Path,
     method: &HashMethod,
     bytes_to_hash: u64,
@@ -14,7 +13,7 @@ pub(crate) fn invoke(
         if path.is_file() {
             hash_file(path, method, bytes_to_hash, buffer_size)?
         } else {
-            anyhow::bail!("Path is not a file: {}", path.display());
+            return Err(Error::NotAFile(path.to_path_buf()));
         }
     };
     print!("{}", hex::encode(hash_root));
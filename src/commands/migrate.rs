@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::format::ManifestFormat;
+use crate::hashers::merkle::SCHEMA_LATEST;
+use crate::hashers::registry::Algorithm;
+use crate::hashers::utils::{Digest, HashMethod};
+use crate::utils::{
+    EntryKind, FileManifest, MemoryManifest, has_manifest, is_manifest_file, manifest_file,
+    manifest_root, open_manifest, write_node_file,
+};
+
+/// Where and how the manifest is stored, it is written back the same way
+enum Stored {
+    /// One node file per directory under this folder
+    Tree(PathBuf),
+    /// Single-file manifest
+    File(PathBuf, ManifestFormat),
+    /// One JSON or CBOR object of nodes
+    Exported(PathBuf, ManifestFormat),
+}
+
+impl Stored {
+    fn locate(path: &Path, manifest_dir: Option<&Path>) -> Result<Self> {
+        let path = match manifest_dir {
+            Some(dir) => dir.to_path_buf(),
+            None if path.is_file() || has_manifest(path) => path.to_path_buf(),
+            None => manifest_root(path, None),
+        };
+        if path.is_file() {
+            if is_manifest_file(&path)? {
+                let encoding = FileManifest::open(&path)?.encoding();
+                return Ok(Stored::File(path, encoding));
+            }
+            let format = ManifestFormat::detect(&fs::read(&path)?);
+            return Ok(Stored::Exported(path, format));
+        }
+        match manifest_file(&path) {
+            Some(file) => {
+                let encoding = FileManifest::open(&file)?.encoding();
+                Ok(Stored::File(file, encoding))
+            }
+            None => Ok(Stored::Tree(path)),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Stored::Tree(path) | Stored::File(path, _) | Stored::Exported(path, _) => path,
+        }
+    }
+
+    /// Write back the nodes of `manifest` listed in `migrated`
+    fn write(&self, manifest: &MemoryManifest, migrated: &[PathBuf]) -> Result<()> {
+        match self {
+            Stored::Tree(root) => {
+                for rel in migrated {
+                    let dir = root.join(rel);
+                    let format = ManifestFormat::ALL
+                        .into_iter()
+                        .find(|f| dir.join(f.node_file()).is_file())
+                        .unwrap_or_default();
+                    write_node_file(&manifest.nodes[rel], &dir, format)?;
+                }
+                Ok(())
+            }
+            Stored::File(path, encoding) => FileManifest::write(manifest, path, *encoding),
+            Stored::Exported(path, ManifestFormat::Json) => {
+                let file = fs::File::create(path)?;
+                serde_json::to_writer_pretty(file, &manifest.nodes)?;
+                Ok(())
+            }
+            Stored::Exported(path, format) => Ok(fs::write(path, format.encode(&manifest.nodes)?)?),
+        }
+    }
+}
+
+/// Upgrade the manifest of `path` to the latest schema version, in place.
+/// `path` can be a dataset, a manifest folder or a manifest file.
+pub fn invoke(path: &Path, manifest_dir: Option<&Path>) -> Result<()> {
+    let stored = Stored::locate(path, manifest_dir)?;
+    let mut manifest = MemoryManifest::load(&*open_manifest(stored.path())?)?;
+    let root = manifest
+        .nodes
+        .get(Path::new(""))
+        .ok_or_else(|| Error::MissingNode(PathBuf::new()))?;
+
+    let mut migrated = Vec::new();
+    let algorithm = HashMethod::from_name(&root.hash_method)?.algorithm();
+    let root_hash = migrate_rec(algorithm, &mut manifest, Path::new(""), &mut migrated)?;
+
+    if migrated.is_empty() {
+        eprintln!("already at schema_version {}", SCHEMA_LATEST);
+    } else {
+        stored.write(&manifest, &migrated)?;
+        eprintln!(
+            "migrated {} of {} node(s) to schema_version {}",
+            migrated.len(),
+            manifest.nodes.len(),
+            SCHEMA_LATEST
+        );
+    }
+    println!("{}", hex::encode(root_hash));
+    Ok(())
+}
+
+/// Bring the node of `rel` and its subtree to the latest schema, returns its
+/// new root. File digests are kept as they are: every version commits to the
+/// same content digest, only the directory trees are rehashed.
+fn migrate_rec(
+    algorithm: &Algorithm,
+    manifest: &mut MemoryManifest,
+    rel: &Path,
+    migrated: &mut Vec<PathBuf>,
+) -> Result<Digest> {
+    let mut node = manifest
+        .nodes
+        .remove(rel)
+        .ok_or_else(|| Error::MissingNode(rel.to_path_buf()))?;
+
+    let mut changed = node.schema_version != SCHEMA_LATEST;
+    for child in &mut node.children {
+        let sub = rel.join(&child.name);
+        let is_dir = manifest.nodes.contains_key(&sub);
+        // Version 1 leaves have no type, the new leaf hash commits to it
+        if child.kind.is_none() {
+            child.kind = Some(if is_dir {
+                EntryKind::Dir
+            } else {
+                EntryKind::File
+            });
+            changed = true;
+        }
+        if is_dir {
+            let root = migrate_rec(algorithm, manifest, &sub, migrated)?;
+            if child.hash != root {
+                child.hash = root;
+                changed = true;
+            }
+        }
+    }
+
+    if changed {
+        node.root_hash = algorithm.merkle_root(&node.children, SCHEMA_LATEST)?;
+        node.schema_version = SCHEMA_LATEST;
+        migrated.push(rel.to_path_buf());
+    }
+    let root = node.root_hash;
+    manifest.nodes.insert(rel.to_path_buf(), node);
+    Ok(root)
+}
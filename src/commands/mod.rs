@@ -1,3 +1,6 @@
-pub(crate) mod build;
-pub(crate) mod diff;
-pub(crate) mod hash;
+pub mod build;
+pub mod diff;
+pub mod hash;
+pub mod migrate;
+pub mod prove;
+pub mod verify;
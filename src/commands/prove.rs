@@ -0,0 +1,460 @@
+use hex::FromHex;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::hashers::chunking::{Chunking, hash_file_content};
+use crate::hashers::merkle::SCHEMA_LATEST;
+use crate::hashers::registry::Algorithm;
+use crate::hashers::sampling::Sampling;
+use crate::hashers::utils::{Digest, HashMethod};
+use crate::utils::{
+    EntryKind, Leaf, ManifestReader, check_schema, legacy_schema_version, manifest_root,
+    open_manifest,
+};
+
+/// Inclusion proof of a single file, self-contained so it can be checked
+/// without the rest of the dataset.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Proof {
+    #[serde(default = "legacy_schema_version")]
+    pub schema_version: u32,
+    pub hash_method: String,
+    pub bytes_to_hash: u64,
+    /// Chunk sizes when the leaf hash is a chunk Merkle root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunking: Option<Chunking>,
+    /// Windows hashed when the leaf hash is sampled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<Sampling>,
+    /// Path of the file relative to the dataset root
+    pub path: String,
+    #[serde(with = "hex::serde")]
+    pub leaf_hash: Digest,
+    #[serde(with = "hex::serde")]
+    pub root_hash: Digest,
+    /// One level per directory, from the file's folder up to the root
+    pub levels: Vec<ProofLevel>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProofLevel {
+    /// Name of the child proven at this level
+    pub name: String,
+    pub index: usize,
+    pub total: usize,
+    /// Target of the child when it is a followed symlink
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Hex encoded sibling hashes, as produced by rs_merkle
+    pub proof_hashes: Vec<String>,
+}
+
+pub fn invoke(dataset: &Path, rel: &Path, manifest_dir: Option<&Path>) -> Result<()> {
+    let proof = prove(dataset, rel, manifest_dir)?;
+    let out = BufWriter::new(std::io::stdout().lock());
+    serde_json::to_writer_pretty(out, &proof)?;
+    println!();
+    Ok(())
+}
+
+pub fn invoke_verify(
+    proof: &Path,
+    file: &Path,
+    root: &str,
+    allow_v1: bool,
+    buffer_size: usize,
+) -> Result<()> {
+    let path = proof;
+    let proof: Proof = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    check_schema(proof.schema_version, path)?;
+    // A v1 root does not commit to names, the file could be proven under
+    // any of them
+    if proof.schema_version < SCHEMA_LATEST && !allow_v1 {
+        return Err(Error::InvalidProof(format!(
+            "schema version {} does not commit to file names, pass --allow-v1 to accept it",
+            proof.schema_version
+        )));
+    }
+    let method = HashMethod::from_name(&proof.hash_method)?;
+    if !method.is_cryptographic() {
+        return Err(Error::NotCryptographic(proof.hash_method));
+    }
+    check_proof(&proof)?;
+    let root = Digest::from_hex(root)?;
+
+    let leaf_hash = hash_file_content(
+        file,
+        &method,
+        proof.bytes_to_hash,
+        buffer_size,
+        proof.chunking.as_ref(),
+        proof.sampling.as_ref(),
+    )?
+    .digest;
+    let computed = proof_root(method.algorithm(), &proof, leaf_hash)?;
+
+    if computed != root {
+        return Err(Error::ProofMismatch {
+            path: file.to_path_buf(),
+            root: hex::encode(root),
+        });
+    }
+    println!("OK: {} belongs to {}", proof.path, hex::encode(root));
+    Ok(())
+}
+
+/// Proof of the file `rel` of `dataset`, from the manifest in
+/// `manifest_dir` when given or in its default folder
+pub fn prove(dataset: &Path, rel: &Path, manifest_dir: Option<&Path>) -> Result<Proof> {
+    let manifest = open_manifest(&manifest_root(dataset, manifest_dir))?;
+    let root = manifest.node(Path::new(""))?;
+    if manifest.is_dir(rel) {
+        return Err(Error::InvalidProof(format!(
+            "{} is a directory, only files can be proven",
+            rel.display()
+        )));
+    }
+
+    // Anyone can forge a file matching a checksum leaf
+    let method = HashMethod::from_name(&root.hash_method)?;
+    if root.non_cryptographic || !method.is_cryptographic() {
+        return Err(Error::NotCryptographic(root.hash_method.clone()));
+    }
+    check_leaves(
+        root.bytes_to_hash,
+        root.chunking.as_ref(),
+        root.sampling.as_ref(),
+    )?;
+    let (leaf_hash, levels) = prove_levels(method.algorithm(), &*manifest, rel)?;
+
+    Ok(Proof {
+        schema_version: root.schema_version,
+        hash_method: root.hash_method.clone(),
+        bytes_to_hash: root.bytes_to_hash,
+        chunking: root.chunking,
+        sampling: root.sampling,
+        path: rel.to_string_lossy().into_owned(),
+        leaf_hash,
+        root_hash: root.root_hash,
+        levels,
+    })
+}
+
+fn prove_levels(
+    algorithm: &Algorithm,
+    manifest: &dyn ManifestReader,
+    rel: &Path,
+) -> Result<(Digest, Vec<ProofLevel>)> {
+    let mut levels = Vec::new();
+    let mut dir = PathBuf::new();
+    let mut leaf_hash = None;
+
+    // Walk down from the root, proving each component in its parent
+    for component in rel.iter() {
+        let name = component.to_string_lossy();
+        let node = manifest.node(&dir)?;
+        let Some(index) = node.children.iter().position(|c| c.name == name) else {
+            return Err(Error::InvalidProof(format!(
+                "{} not found in the manifest",
+                rel.display()
+            )));
+        };
+
+        let child = &node.children[index];
+        if child.kind == Some(EntryKind::Symlink) {
+            return Err(Error::InvalidProof(format!(
+                "{} goes through a symlink that is not followed",
+                rel.display()
+            )));
+        }
+        let proof = algorithm.merkle_proof(&node.children, index, node.schema_version)?;
+
+        levels.push(ProofLevel {
+            name: name.into_owned(),
+            index,
+            total: node.children.len(),
+            target: child.target.clone(),
+            proof_hashes: proof.iter().map(hex::encode).collect(),
+        });
+        leaf_hash = Some(child.hash);
+        dir.push(component);
+    }
+
+    let Some(leaf_hash) = leaf_hash else {
+        return Err(Error::InvalidProof("empty path, nothing to prove".into()));
+    };
+    // Proofs are checked bottom up
+    levels.reverse();
+
+    Ok((leaf_hash, levels))
+}
+
+/// Fold the proof levels from the leaf up to the dataset root, once
+/// `check_proof` accepted them
+fn proof_root(algorithm: &Algorithm, proof: &Proof, leaf_hash: Digest) -> Result<Digest> {
+    let mut current = leaf_hash;
+    // Only the bottom level proves a file, the others prove directories
+    let mut kind = EntryKind::File;
+    for level in &proof.levels {
+        let hashes = level
+            .proof_hashes
+            .iter()
+            .map(Digest::from_hex)
+            .collect::<Result<Vec<_>>>()?;
+        let leaf = Leaf {
+            name: level.name.clone(),
+            hash: current,
+            kind: Some(kind),
+            target: level.target.clone(),
+            stat: None,
+            chunks: None,
+        };
+        current = algorithm.merkle_proof_root(
+            &hashes,
+            level.index,
+            level.total,
+            &leaf,
+            proof.schema_version,
+        )?;
+        kind = EntryKind::Dir;
+    }
+    Ok(current)
+}
+
+/// Reject the proofs the dataset root does not vouch for
+fn check_proof(proof: &Proof) -> Result<()> {
+    check_leaves(
+        proof.bytes_to_hash,
+        proof.chunking.as_ref(),
+        proof.sampling.as_ref(),
+    )?;
+    check_levels(proof)
+}
+
+/// The leaves only record their digest, not how it was computed. A proof
+/// claiming a prefix or a chunking that the dataset did not use would make
+/// another file verify, so only whole file digests are proven. A sampled
+/// digest does not even commit to the whole file.
+fn check_leaves(
+    bytes_to_hash: u64,
+    chunking: Option<&Chunking>,
+    sampling: Option<&Sampling>,
+) -> Result<()> {
+    if bytes_to_hash != 0 {
+        return Err(Error::NotProvable(format!(
+            "a dataset hashed from the first {bytes_to_hash} bytes of its files"
+        )));
+    }
+    if chunking.is_some() {
+        return Err(Error::NotProvable("a chunked dataset".into()));
+    }
+    if sampling.is_some() {
+        return Err(Error::NotProvable("a sampled dataset".into()));
+    }
+    Ok(())
+}
+
+/// The levels must prove the components of `path`, bottom up, each at an
+/// index within its directory
+fn check_levels(proof: &Proof) -> Result<()> {
+    if proof.levels.is_empty() {
+        return Err(Error::InvalidProof("no levels, nothing is proven".into()));
+    }
+    let names = Path::new(&proof.path).iter().rev();
+    if !names.eq(proof.levels.iter().map(|l| OsStr::new(&l.name))) {
+        return Err(Error::InvalidProof(format!(
+            "the levels do not prove {}",
+            proof.path
+        )));
+    }
+    if let Some(level) = proof.levels.iter().find(|l| l.index >= l.total) {
+        return Err(Error::InvalidProof(format!(
+            "index {} of {} is out of its {} children",
+            level.index, level.name, level.total
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::DatasetHasher;
+    use crate::utils::write_dataset;
+
+    const LAYOUT: &[(&str, &str)] = &[
+        ("a/b/file.txt", "proven"),
+        ("a/other.txt", "sibling"),
+        ("top.txt", "top"),
+    ];
+
+    /// Store the manifest of `dir`, returns its root
+    fn build(dir: &Path) -> Digest {
+        DatasetHasher::new(dir)
+            .method(HashMethod::Sha256)
+            .build()
+            .unwrap()
+            .root
+    }
+
+    fn check(proof: &Proof) -> Result<Digest> {
+        check_proof(proof)?;
+        let method = HashMethod::from_name(&proof.hash_method)?;
+        proof_root(method.algorithm(), proof, proof.leaf_hash)
+    }
+
+    #[test]
+    fn proof_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dataset(dir.path(), LAYOUT);
+        let root = build(dir.path());
+        let proof = prove(dir.path(), Path::new("a/b/file.txt"), None).unwrap();
+        assert_eq!(proof.root_hash, root);
+        assert_eq!(proof.levels.len(), 3);
+        assert_eq!(check(&proof).unwrap(), root);
+
+        let leaf = hash_file_content(
+            &dir.path().join("a/b/file.txt"),
+            &HashMethod::Sha256,
+            0,
+            8192,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(leaf.digest, proof.leaf_hash);
+    }
+
+    #[test]
+    fn proofs_read_the_manifest_where_it_was_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        let manifests = dir.path().join("manifests");
+        write_dataset(&data, &[("a/file.txt", "proven")]);
+        let root = DatasetHasher::new(&data)
+            .method(HashMethod::Sha256)
+            .manifest_dir(&manifests)
+            .build()
+            .unwrap()
+            .root;
+        let rel = Path::new("a/file.txt");
+        assert!(prove(&data, rel, None).is_err());
+        let proof = prove(&data, rel, Some(&manifests)).unwrap();
+        assert_eq!(check(&proof).unwrap(), root);
+
+        // Next to an archive
+        let archive = dir.path().join("data.tar");
+        let mut tar = tar::Builder::new(File::create(&archive).unwrap());
+        tar.append_dir_all(".", &data).unwrap();
+        tar.into_inner().unwrap();
+        let built = DatasetHasher::new(&archive)
+            .method(HashMethod::Sha256)
+            .build()
+            .unwrap()
+            .root;
+        assert_eq!(built, root);
+        let proof = prove(&archive, rel, None).unwrap();
+        assert_eq!(check(&proof).unwrap(), root);
+    }
+
+    #[test]
+    fn tampered_proof_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dataset(dir.path(), LAYOUT);
+        let root = build(dir.path());
+        let proof = || prove(dir.path(), Path::new("a/b/file.txt"), None).unwrap();
+
+        let mut other_leaf = proof();
+        other_leaf.leaf_hash = HashMethod::Sha256.algorithm().hash_bytes(b"forged");
+        assert_ne!(check(&other_leaf).unwrap(), root);
+
+        let mut sibling = proof();
+        sibling.levels[1].proof_hashes[0] = hex::encode(other_leaf.leaf_hash);
+        assert_ne!(check(&sibling).unwrap(), root);
+
+        // Same levels under another name
+        let mut renamed = proof();
+        renamed.levels[0].name = "renamed.txt".into();
+        renamed.path = "a/b/renamed.txt".into();
+        assert_ne!(check(&renamed).unwrap(), root);
+
+        let mut moved = proof();
+        moved.path = "a/file.txt".into();
+        assert!(matches!(check(&moved), Err(Error::InvalidProof(_))));
+
+        let mut out_of_range = proof();
+        out_of_range.levels[2].index = out_of_range.levels[2].total;
+        assert!(matches!(check(&out_of_range), Err(Error::InvalidProof(_))));
+
+        let mut empty = proof();
+        empty.levels.clear();
+        assert!(matches!(check(&empty), Err(Error::InvalidProof(_))));
+
+        // The root does not commit to how the leaf was hashed
+        let mut prefix = proof();
+        prefix.bytes_to_hash = 6;
+        assert!(matches!(check(&prefix), Err(Error::NotProvable(_))));
+
+        let mut chunked = proof();
+        chunked.chunking = Some(Chunking::default());
+        assert!(matches!(check(&chunked), Err(Error::NotProvable(_))));
+
+        let mut sampled = proof();
+        sampled.sampling = Some(Sampling::default());
+        assert!(matches!(check(&sampled), Err(Error::NotProvable(_))));
+    }
+
+    #[test]
+    fn directories_checksums_and_partial_hashes_are_not_proven() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dataset(dir.path(), LAYOUT);
+        build(dir.path());
+        assert!(matches!(
+            prove(dir.path(), Path::new("a/b"), None),
+            Err(Error::InvalidProof(_))
+        ));
+
+        DatasetHasher::new(dir.path())
+            .method(HashMethod::Sha256)
+            .bytes_to_hash(4)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            prove(dir.path(), Path::new("top.txt"), None),
+            Err(Error::NotProvable(_))
+        ));
+
+        DatasetHasher::new(dir.path())
+            .method(HashMethod::Sha256)
+            .chunking(Chunking::default())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            prove(dir.path(), Path::new("top.txt"), None),
+            Err(Error::NotProvable(_))
+        ));
+
+        DatasetHasher::new(dir.path())
+            .method(HashMethod::Sha256)
+            .sampling(Sampling::default())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            prove(dir.path(), Path::new("top.txt"), None),
+            Err(Error::NotProvable(_))
+        ));
+
+        DatasetHasher::new(dir.path())
+            .method(HashMethod::Xxh3)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            prove(dir.path(), Path::new("top.txt"), None),
+            Err(Error::NotCryptographic(_))
+        ));
+    }
+}
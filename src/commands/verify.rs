@@ -0,0 +1,82 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::commands::build::generic_build;
+use crate::commands::diff::{Diff, diff_rec};
+use crate::error::{Error, Result};
+use crate::filter::{PathFilter, Patterns};
+use crate::hashers::utils::HashMethod;
+use crate::utils::{BuildOptions, manifest_root, open_manifest};
+
+pub fn invoke(
+    path: &Path,
+    manifest_dir: Option<&Path>,
+    buffer_size: usize,
+    num_workers: usize,
+    patterns: &Patterns,
+) -> Result<()> {
+    let stored = open_manifest(&manifest_root(path, manifest_dir))?;
+    let root = stored.node(Path::new(""))?;
+    if let Some(recorded) = &root.dataset_root
+        && *recorded != fs::canonicalize(path)?
+    {
+        eprintln!(
+            "warning: the manifest was built from {}, not {}",
+            recorded.display(),
+            path.display()
+        );
+    }
+
+    // Rehash with the same settings as the stored manifest
+    let opts = BuildOptions {
+        method: HashMethod::from_name(&root.hash_method)?,
+        bytes_to_hash: root.bytes_to_hash,
+        chunking: root.chunking,
+        sampling: root.sampling,
+        buffer_size,
+        num_workers,
+        store: false,
+        incremental: false,
+        keep_going: false,
+        patterns: root.patterns.clone().unwrap_or_default(),
+        symlinks: root.symlinks.unwrap_or_default(),
+        schema_version: root.schema_version,
+        ..BuildOptions::default()
+    };
+    let fresh = generic_build(path, &opts)?.manifest;
+
+    let mut out = Diff::default();
+    // The command line patterns only narrow down what is reported
+    let filter = PathFilter::new(patterns)?;
+    diff_rec(&*stored, &fresh, Path::new(""), &filter, &mut out)?;
+    if out.is_empty() {
+        return Ok(());
+    }
+
+    for k in &out.removed {
+        println!("Missing: {}", k.path);
+    }
+    for k in &out.added {
+        println!("Extra: {}", k.path);
+    }
+    for k in &out.changed {
+        println!("Mismatch: {}", k.path);
+    }
+    for k in &out.retargeted {
+        println!("Retargeted: {}", k.path);
+    }
+    Err(Error::VerifyFailed {
+        path: path.to_path_buf(),
+        missing: out.removed.len(),
+        extra: out.added.len(),
+        // A retargeted link usually differs in content as well
+        mismatched: out
+            .changed
+            .iter()
+            .chain(&out.retargeted)
+            .map(|k| &k.path)
+            .collect::<BTreeSet<_>>()
+            .len(),
+    })
+}
@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use crate::archive;
+use crate::commands::build::generic_build;
+use crate::error::{Error, Result};
+use crate::format::ManifestFormat;
+use crate::hashers::chunking::Chunking;
+use crate::hashers::sampling::Sampling;
+use crate::hashers::utils::HashMethod;
+use crate::utils::{BuildOptions, BuildOutput, ManifestLayout, SymlinkMode};
+
+/// Builder around a dataset build, defaults match the `rush build` CLI.
+pub struct DatasetHasher {
+    path: PathBuf,
+    opts: BuildOptions,
+}
+
+impl DatasetHasher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        DatasetHasher {
+            path: path.into(),
+            opts: BuildOptions::default(),
+        }
+    }
+
+    pub fn method(mut self, method: HashMethod) -> Self {
+        self.opts.method = method;
+        self
+    }
+
+    /// Number of bytes to hash per file, 0 hashes the full content
+    pub fn bytes_to_hash(mut self, bytes_to_hash: u64) -> Self {
+        self.opts.bytes_to_hash = bytes_to_hash;
+        self
+    }
+
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.opts.buffer_size = buffer_size;
+        self
+    }
+
+    pub fn num_workers(mut self, num_workers: usize) -> Self {
+        self.opts.num_workers = num_workers;
+        self
+    }
+
+    /// Write the manifest under `.rush`, otherwise it is returned in memory
+    pub fn store(mut self, store: bool) -> Self {
+        self.opts.store = store;
+        self
+    }
+
+    /// Write the manifest in `dir` instead of `.rush`
+    pub fn manifest_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.opts.manifest_dir = Some(dir.into());
+        self
+    }
+
+    /// One `merkle.json` per directory or a single indexed file
+    pub fn manifest_layout(mut self, layout: ManifestLayout) -> Self {
+        self.opts.layout = layout;
+        self
+    }
+
+    /// JSON or compact CBOR manifest
+    pub fn manifest_format(mut self, format: ManifestFormat) -> Self {
+        self.opts.format = format;
+        self
+    }
+
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.opts.incremental = incremental;
+        self
+    }
+
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.opts.keep_going = keep_going;
+        self
+    }
+
+    /// Hash files as the Merkle root of their content-defined chunks
+    pub fn chunking(mut self, chunking: Chunking) -> Self {
+        self.opts.chunking = Some(chunking);
+        self
+    }
+
+    /// Hash files from sampled windows instead of their whole content
+    pub fn sampling(mut self, sampling: Sampling) -> Self {
+        self.opts.sampling = Some(sampling);
+        self
+    }
+
+    pub fn symlinks(mut self, mode: SymlinkMode) -> Self {
+        self.opts.symlinks = mode;
+        self
+    }
+
+    /// Leave out the paths matching a gitignore-style pattern
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.opts.patterns.exclude.push(pattern.into());
+        self
+    }
+
+    /// Keep the paths matching a pattern even if excluded
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.opts.patterns.include.push(pattern.into());
+        self
+    }
+
+    pub fn build(&self) -> Result<BuildOutput> {
+        if !self.path.is_dir() && !archive::is_archive(&self.path)? {
+            return Err(Error::NotADirectory(self.path.clone()));
+        }
+        generic_build(&self.path, &self.opts)
+    }
+}
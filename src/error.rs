@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use crate::utils::FailedFile;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Hex(#[from] hex::FromHexError),
+    #[error("cbor: {0}")]
+    Cbor(String),
+    #[error(transparent)]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    #[error(transparent)]
+    Merkle(#[from] rs_merkle::Error),
+    #[error(transparent)]
+    StripPrefix(#[from] std::path::StripPrefixError),
+
+    #[error("unknown hash method: {0}")]
+    UnknownHashMethod(String),
+    #[error("hash method {0} is already registered")]
+    DuplicateHashMethod(String),
+    #[error("Expected {expected}-byte digest for {method}")]
+    DigestMismatch {
+        method: &'static str,
+        expected: usize,
+    },
+    #[error("Invalid hex string length: {0} bytes")]
+    InvalidDigestLength(usize),
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(String),
+    #[error("invalid chunk size {size}, the average must be between {min} and {max} bytes")]
+    InvalidChunkSize { size: u32, min: u32, max: u32 },
+    #[error("invalid sampling: {0}")]
+    InvalidSampling(String),
+    #[error("at least one worker is needed to hash the files")]
+    NoWorkers,
+    #[error("Merkle tree has no root (empty tree?)")]
+    EmptyTree,
+
+    #[error("incorrect path: {}\n should be a directory", .0.display())]
+    NotADirectory(PathBuf),
+    #[error("Path is not a file: {}", .0.display())]
+    NotAFile(PathBuf),
+    #[error("symbolic link found: {} (--symlinks=error)", .0.display())]
+    Symlink(PathBuf),
+    #[error("failed to hash:{}", failed_list(.0))]
+    HashFailed(Vec<FailedFile>),
+    #[error("invalid archive {}: {reason}", .path.display())]
+    InvalidArchive { path: PathBuf, reason: String },
+    #[error("no node for {}", .0.display())]
+    MissingNode(PathBuf),
+    #[error("invalid manifest: {0}")]
+    InvalidManifest(String),
+    #[error(
+        "{}: unsupported schema_version {version}, this rush reads versions 1 to {latest}",
+        .path.display()
+    )]
+    UnsupportedSchema {
+        path: PathBuf,
+        version: u32,
+        latest: u32,
+    },
+
+    #[error("Hash methods differ: {0} vs {1}")]
+    HashMethodMismatch(String, String),
+    #[error("Bytes to hash differ at {}: {lhs} vs {rhs}", .path.display())]
+    BytesToHashMismatch { path: PathBuf, lhs: u64, rhs: u64 },
+
+    #[error("Chunking differs at {}: {lhs} vs {rhs}", .path.display())]
+    ChunkingMismatch {
+        path: PathBuf,
+        lhs: String,
+        rhs: String,
+    },
+
+    #[error("Sampling differs at {}: {lhs} vs {rhs}", .path.display())]
+    SamplingMismatch {
+        path: PathBuf,
+        lhs: String,
+        rhs: String,
+    },
+
+    #[error(
+        "{} does not match its manifest ({missing} missing, {extra} extra, {mismatched} mismatched)",
+        .path.display()
+    )]
+    VerifyFailed {
+        path: PathBuf,
+        missing: usize,
+        extra: usize,
+        mismatched: usize,
+    },
+    #[error("{0} is not a cryptographic hash, inclusion proofs need one")]
+    NotCryptographic(String),
+    #[error("{0} cannot be proven, the root does not commit to how its files were hashed")]
+    NotProvable(String),
+    #[error("invalid proof: {0}")]
+    InvalidProof(String),
+    #[error("{} is not part of the dataset with root {root}", .path.display())]
+    ProofMismatch { path: PathBuf, root: String },
+}
+
+fn failed_list(failed: &[FailedFile]) -> String {
+    failed
+        .iter()
+        .map(|f| format!("\n  {}: {}", f.path.display(), f.error))
+        .collect()
+}
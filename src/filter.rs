@@ -0,0 +1,150 @@
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+
+/// Name of the per-directory ignore file, gitignore syntax
+pub const RUSHIGNORE: &str = ".rushignore";
+
+/// Patterns given with `--exclude`/`--include`, recorded in the root manifest
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Patterns {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+}
+
+impl Patterns {
+    pub fn is_empty(&self) -> bool {
+        self.exclude.is_empty() && self.include.is_empty()
+    }
+}
+
+/// Compiled `Patterns`, matched against paths relative to the dataset root.
+/// Includes are added last so they win over excludes, like `!pattern` lines
+/// in a gitignore file.
+#[derive(Clone)]
+pub struct PathFilter {
+    rules: Gitignore,
+}
+
+impl PathFilter {
+    pub fn new(patterns: &Patterns) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(".");
+        for p in &patterns.exclude {
+            builder.add_line(None, p).map_err(invalid_pattern)?;
+        }
+        for p in &patterns.include {
+            builder
+                .add_line(None, &format!("!{}", p))
+                .map_err(invalid_pattern)?;
+        }
+        Ok(PathFilter {
+            rules: builder.build().map_err(invalid_pattern)?,
+        })
+    }
+
+    fn matched(&self, rel: &Path, is_dir: bool) -> Match<()> {
+        match self.rules.matched(rel, is_dir) {
+            Match::None => Match::None,
+            Match::Ignore(_) => Match::Ignore(()),
+            Match::Whitelist(_) => Match::Whitelist(()),
+        }
+    }
+
+    /// Whether a reported path, or one of its parents, is excluded
+    pub fn is_excluded(&self, rel: &Path, is_dir: bool) -> bool {
+        self.rules
+            .matched_path_or_any_parents(rel, is_dir)
+            .is_ignore()
+    }
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        PathFilter {
+            rules: Gitignore::empty(),
+        }
+    }
+}
+
+fn invalid_pattern(err: ignore::Error) -> Error {
+    Error::InvalidPattern(err.to_string())
+}
+
+/// Rules active in a directory while walking the dataset: the command line
+/// patterns, then every `.rushignore` from the deepest directory up to the
+/// root. The first rule that matches decides.
+#[derive(Clone)]
+pub struct IgnoreRules {
+    filter: PathFilter,
+    rushignores: Vec<Arc<Gitignore>>,
+}
+
+impl IgnoreRules {
+    pub fn new(filter: PathFilter) -> Self {
+        IgnoreRules {
+            filter,
+            rushignores: Vec::new(),
+        }
+    }
+
+    /// Rules for the directory `dir`, picking up its `.rushignore`
+    pub fn enter(&self, dir: &Path) -> Result<Self> {
+        let file = dir.join(RUSHIGNORE);
+        if !file.is_file() {
+            return Ok(self.clone());
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(err) = builder.add(&file) {
+            return Err(invalid_pattern(err));
+        }
+        let mut rules = self.clone();
+        rules
+            .rushignores
+            .push(Arc::new(builder.build().map_err(invalid_pattern)?));
+        Ok(rules)
+    }
+
+    /// Same as `enter` for a directory that is not on disk, given the
+    /// content of its `.rushignore`
+    pub fn enter_with(&self, dir: &Path, rushignore: Option<&str>) -> Result<Self> {
+        let Some(rushignore) = rushignore else {
+            return Ok(self.clone());
+        };
+        let file = dir.join(RUSHIGNORE);
+        let mut builder = GitignoreBuilder::new(dir);
+        for line in rushignore.lines() {
+            builder
+                .add_line(Some(file.clone()), line)
+                .map_err(invalid_pattern)?;
+        }
+        let mut rules = self.clone();
+        rules
+            .rushignores
+            .push(Arc::new(builder.build().map_err(invalid_pattern)?));
+        Ok(rules)
+    }
+
+    /// `path` is the full path of the entry, `rel` the same path relative to
+    /// the dataset root
+    pub fn is_ignored(&self, path: &Path, rel: &Path, is_dir: bool) -> bool {
+        match self.filter.matched(rel, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+        for rules in self.rushignores.iter().rev() {
+            match rules.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,70 @@
+use clap::ValueEnum;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Encoding of the stored manifest nodes. Readers detect it from the first
+/// bytes of each file, the option only matters when writing.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestFormat {
+    /// Human readable, hex digests
+    #[default]
+    Json,
+    /// Compact binary, raw digest bytes
+    #[value(alias = "binary")]
+    Cbor,
+}
+
+/// CBOR self-describe tag, written at the start of every CBOR file
+const CBOR_MAGIC: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+impl ManifestFormat {
+    pub const ALL: [ManifestFormat; 2] = [ManifestFormat::Json, ManifestFormat::Cbor];
+
+    /// Name of the per-directory node file
+    pub fn node_file(self) -> &'static str {
+        match self {
+            ManifestFormat::Json => "merkle.json",
+            ManifestFormat::Cbor => "merkle.cbor",
+        }
+    }
+
+    /// Name of the single-file manifest
+    pub fn manifest_file(self) -> &'static str {
+        match self {
+            ManifestFormat::Json => "manifest.ndjson",
+            ManifestFormat::Cbor => "manifest.cbor",
+        }
+    }
+
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&CBOR_MAGIC) {
+            ManifestFormat::Cbor
+        } else {
+            ManifestFormat::Json
+        }
+    }
+
+    /// Compact encoding of `value`, prefixed with the magic for CBOR
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            ManifestFormat::Json => Ok(serde_json::to_vec(value)?),
+            ManifestFormat::Cbor => {
+                let mut out = CBOR_MAGIC.to_vec();
+                ciborium::into_writer(value, &mut out).map_err(|e| Error::Cbor(e.to_string()))?;
+                Ok(out)
+            }
+        }
+    }
+
+    /// Decode `bytes` in whichever format they were written
+    pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        match ManifestFormat::detect(bytes) {
+            ManifestFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            ManifestFormat::Cbor => ciborium::from_reader(&bytes[CBOR_MAGIC.len()..])
+                .map_err(|e| Error::Cbor(e.to_string())),
+        }
+    }
+}
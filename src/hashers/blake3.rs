@@ -1,12 +1,9 @@
-use anyhow::{Ok, Result, bail};
 use blake3::Hasher;
 use rs_merkle::Hasher as Mh;
-use std::{
-    fs::File,
-    io::{BufReader, Read},
-    path::Path,
-};
+use std::io::{BufReader, Read};
 
+use crate::error::{Error, Result};
+use crate::hashers::registry::HashAlgorithm;
 use crate::hashers::utils::{Digest, DigestCompatibleHasher};
 #[derive(Clone)]
 pub struct Blake3Algorithm {}
@@ -29,7 +26,10 @@ impl DigestCompatibleHasher for Blake3Algorithm {
     fn from_digest(digest: &Digest) -> Result<Self::Hash> {
         match digest {
             Digest::D32(arr) => Ok(*arr),
-            _ => bail!("Expected 32-byte digest for Blake3"),
+            _ => Err(Error::DigestMismatch {
+                method: "blake3",
+                expected: 32,
+            }),
         }
     }
 
@@ -38,8 +38,20 @@ impl DigestCompatibleHasher for Blake3Algorithm {
     }
 }
 
-pub fn blake3_hash_file(path: &Path, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
-    let file = File::open(path)?;
+impl HashAlgorithm for Blake3Algorithm {
+    const NAME: &'static str = "blake3";
+    const DIGEST_LEN: usize = 32;
+
+    fn hash_reader(reader: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
+        blake3_hash_reader(reader, bytes_to_hash, buffer_size)
+    }
+}
+
+pub fn blake3_hash_reader(
+    file: impl Read,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+) -> Result<Digest> {
     let mut hasher = Hasher::new();
 
     if bytes_to_hash > 0 {
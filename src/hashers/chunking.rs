@@ -0,0 +1,177 @@
+use fastcdc::v2020::{AVERAGE_MAX, AVERAGE_MIN, StreamCDC};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::hashers::merkle::chunk_root;
+use crate::hashers::sampling::{Forward, Sampling, hash_file_sampled, hash_sampled};
+use crate::hashers::utils::{Digest, DigestCompatibleHasher, HashMethod, hash_reader};
+
+/// FastCDC chunk size bounds of a chunked build, recorded in every node
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunking {
+    pub min_size: u32,
+    pub avg_size: u32,
+    pub max_size: u32,
+}
+
+impl Chunking {
+    pub const DEFAULT_AVG_SIZE: u32 = 1 << 20;
+
+    /// Bounds around `avg_size`, a quarter and four times it as FastCDC
+    /// recommends
+    pub fn new(avg_size: u32) -> Result<Self> {
+        if !(AVERAGE_MIN..=AVERAGE_MAX).contains(&avg_size) {
+            return Err(Error::InvalidChunkSize {
+                size: avg_size,
+                min: AVERAGE_MIN,
+                max: AVERAGE_MAX,
+            });
+        }
+        Ok(Chunking {
+            min_size: avg_size / 4,
+            avg_size,
+            max_size: avg_size * 4,
+        })
+    }
+}
+
+impl Default for Chunking {
+    fn default() -> Self {
+        Chunking::new(Chunking::DEFAULT_AVG_SIZE).expect("valid default chunk size")
+    }
+}
+
+impl fmt::Display for Chunking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "chunks of {}/{}/{} bytes",
+            self.min_size, self.avg_size, self.max_size
+        )
+    }
+}
+
+/// A content-defined chunk of a file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk {
+    pub offset: u64,
+    pub length: u64,
+    pub hash: Digest,
+}
+
+/// Digest of a file. When chunking, the digest is the Merkle root of the
+/// chunk digests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHash {
+    pub digest: Digest,
+    pub chunks: Option<Vec<Chunk>>,
+}
+
+impl FileHash {
+    pub fn new(digest: Digest) -> Self {
+        FileHash {
+            digest,
+            chunks: None,
+        }
+    }
+}
+
+/// Hash `reader` as a whole, chunk by chunk with `chunking` or from the
+/// windows of `sampling`. `size` is the length of the content, hashed when
+/// sampling and by methods with a header like `sha1`.
+pub fn hash_content(
+    reader: impl Read,
+    size: u64,
+    method: &HashMethod,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+    chunking: Option<&Chunking>,
+    sampling: Option<&Sampling>,
+) -> Result<FileHash> {
+    if let Some(sampling) = sampling {
+        let digest = hash_sampled(Forward::new(reader), size, method, sampling)?;
+        return Ok(FileHash::new(digest));
+    }
+    match chunking {
+        Some(chunking) => hash_chunks(reader, method, bytes_to_hash, chunking),
+        None => Ok(FileHash::new(hash_reader(
+            reader,
+            size,
+            method,
+            bytes_to_hash,
+            buffer_size,
+        )?)),
+    }
+}
+
+/// Same as `hash_content` for a file on disk
+pub fn hash_file_content(
+    path: &Path,
+    method: &HashMethod,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+    chunking: Option<&Chunking>,
+    sampling: Option<&Sampling>,
+) -> Result<FileHash> {
+    if let Some(sampling) = sampling {
+        // Files can seek, the skipped bytes are not read
+        return Ok(FileHash::new(hash_file_sampled(path, method, sampling)?));
+    }
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    hash_content(
+        file,
+        size,
+        method,
+        bytes_to_hash,
+        buffer_size,
+        chunking,
+        None,
+    )
+}
+
+/// Split the content of `reader` with FastCDC and hash every chunk. Only
+/// the first `bytes_to_hash` bytes are read, unless it is 0.
+pub fn hash_chunks(
+    reader: impl Read,
+    method: &HashMethod,
+    bytes_to_hash: u64,
+    chunking: &Chunking,
+) -> Result<FileHash> {
+    let mut reader: Box<dyn Read> = if bytes_to_hash > 0 {
+        Box::new(reader.take(bytes_to_hash))
+    } else {
+        Box::new(reader)
+    };
+    method.algorithm().hash_chunks(&mut reader, chunking)
+}
+
+pub(crate) fn chunks_with<H>(reader: &mut dyn Read, chunking: &Chunking) -> Result<FileHash>
+where
+    H: DigestCompatibleHasher,
+{
+    let cdc = StreamCDC::new(
+        reader,
+        chunking.min_size,
+        chunking.avg_size,
+        chunking.max_size,
+    );
+    let mut chunks = Vec::new();
+    for chunk in cdc {
+        let chunk = chunk.map_err(std::io::Error::from)?;
+        chunks.push(Chunk {
+            offset: chunk.offset,
+            length: chunk.length as u64,
+            hash: H::to_digest(H::hash(&chunk.data)),
+        });
+    }
+    let digests: Vec<Digest> = chunks.iter().map(|c| c.hash).collect();
+    Ok(FileHash {
+        digest: chunk_root::<H>(&digests)?,
+        chunks: Some(chunks),
+    })
+}
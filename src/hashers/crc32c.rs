@@ -0,0 +1,85 @@
+use rs_merkle::Hasher as Mh;
+use std::io::{self, BufReader, Read, Write};
+
+use crate::error::{Error, Result};
+use crate::hashers::registry::HashAlgorithm;
+use crate::hashers::utils::{Digest, DigestCompatibleHasher};
+
+/// CRC32C (Castagnoli), hardware accelerated on most CPUs. Not
+/// cryptographic and only 32 bits: fast change detection on trusted storage
+/// only.
+#[derive(Clone)]
+pub struct Crc32cAlgorithm {}
+
+impl Mh for Crc32cAlgorithm {
+    type Hash = [u8; 4];
+
+    fn hash(data: &[u8]) -> Self::Hash {
+        ::crc32c::crc32c(data).to_be_bytes()
+    }
+}
+
+impl DigestCompatibleHasher for Crc32cAlgorithm {
+    fn to_digest(hash: Self::Hash) -> Digest {
+        Digest::D4(hash)
+    }
+
+    fn from_digest(digest: &Digest) -> Result<Self::Hash> {
+        match digest {
+            Digest::D4(arr) => Ok(*arr),
+            _ => Err(Error::DigestMismatch {
+                method: "crc32c",
+                expected: 4,
+            }),
+        }
+    }
+
+    fn zero_digest() -> Digest {
+        Digest::D4([0u8; 4])
+    }
+}
+
+/// Lets `io::copy` update the running checksum
+struct Crc32cWriter(u32);
+
+impl Write for Crc32cWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 = ::crc32c::crc32c_append(self.0, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HashAlgorithm for Crc32cAlgorithm {
+    const NAME: &'static str = "crc32c";
+    const DIGEST_LEN: usize = 4;
+    const CRYPTOGRAPHIC: bool = false;
+
+    fn hash_reader(reader: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
+        crc32c_hash_reader(reader, bytes_to_hash, buffer_size)
+    }
+}
+
+pub fn crc32c_hash_reader(
+    file: impl Read,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+) -> Result<Digest> {
+    let mut hasher = Crc32cWriter(0);
+
+    if bytes_to_hash > 0 {
+        // Hash only up to bytes_to_hash
+        // Read at most bytes_to_hash bytes
+        let taker = file.take(bytes_to_hash);
+        let mut reader = BufReader::with_capacity(buffer_size, taker);
+        std::io::copy(&mut reader, &mut hasher)?;
+    } else {
+        let mut reader = BufReader::with_capacity(buffer_size, file);
+        std::io::copy(&mut reader, &mut hasher)?;
+    }
+
+    Ok(Digest::D4(hasher.0.to_be_bytes()))
+}
@@ -1,12 +1,9 @@
-use anyhow::{Result, bail};
 use openssl::hash::{Hasher, MessageDigest};
 use rs_merkle::Hasher as Mh;
-use std::{
-    fs::File,
-    io::{BufReader, Read},
-    path::Path,
-};
+use std::io::{BufReader, Read};
 
+use crate::error::{Error, Result};
+use crate::hashers::registry::HashAlgorithm;
 use crate::hashers::utils::{Digest, DigestCompatibleHasher};
 
 #[derive(Clone)]
@@ -33,7 +30,10 @@ impl DigestCompatibleHasher for Md5Algorithm {
     fn from_digest(digest: &Digest) -> Result<Self::Hash> {
         match digest {
             Digest::D16(arr) => Ok(*arr),
-            _ => bail!("Expected 16-byte digest for Blake3"),
+            _ => Err(Error::DigestMismatch {
+                method: "md5",
+                expected: 16,
+            }),
         }
     }
 
@@ -42,8 +42,16 @@ impl DigestCompatibleHasher for Md5Algorithm {
     }
 }
 
-pub fn md5_hash_file(path: &Path, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
-    let file = File::open(path)?;
+impl HashAlgorithm for Md5Algorithm {
+    const NAME: &'static str = "md5";
+    const DIGEST_LEN: usize = 16;
+
+    fn hash_reader(reader: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
+        md5_hash_reader(reader, bytes_to_hash, buffer_size)
+    }
+}
+
+pub fn md5_hash_reader(file: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
     let mut hasher = Hasher::new(MessageDigest::md5())?;
 
     if bytes_to_hash > 0 {
@@ -58,5 +66,8 @@ pub fn md5_hash_file(path: &Path, bytes_to_hash: u64, buffer_size: usize) -> Res
     };
 
     let digest = hasher.finish()?;
-    Ok(Digest::D16((*digest).try_into()?))
+    let digest = (*digest)
+        .try_into()
+        .map_err(|_| Error::InvalidDigestLength(digest.len()))?;
+    Ok(Digest::D16(digest))
 }
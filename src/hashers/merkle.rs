@@ -0,0 +1,332 @@
+use rs_merkle::{Hasher, MerkleProof, MerkleTree};
+use std::marker::PhantomData;
+
+use crate::error::{Error, Result};
+use crate::hashers::utils::{Digest, DigestCompatibleHasher};
+use crate::utils::{EntryKind, Leaf};
+
+/// First manifest version, only the children digests go into the tree
+pub const SCHEMA_V1: u32 = 1;
+/// Leaves commit to (name, type, digest), leaves and inner nodes are domain
+/// separated
+pub const SCHEMA_V2: u32 = 2;
+/// Version written by builds, and the newest one this build can read
+pub const SCHEMA_LATEST: u32 = SCHEMA_V2;
+
+const LEAF_TAG: u8 = 0x00;
+const NODE_TAG: u8 = 0x01;
+
+/// Wraps a hasher so inner Merkle nodes are hashed as `H(0x01 || l || r)`
+#[derive(Clone)]
+pub struct Tagged<H> {
+    _hasher: PhantomData<H>,
+}
+
+impl<H: Hasher> Hasher for Tagged<H> {
+    type Hash = H::Hash;
+
+    fn hash(data: &[u8]) -> Self::Hash {
+        H::hash(data)
+    }
+
+    fn concat_and_hash(left: &Self::Hash, right: Option<&Self::Hash>) -> Self::Hash {
+        match right {
+            Some(right) => {
+                let mut data: Vec<u8> = vec![NODE_TAG];
+                data.extend_from_slice(&Into::<Vec<u8>>::into(*left));
+                data.extend_from_slice(&Into::<Vec<u8>>::into(*right));
+                H::hash(&data)
+            }
+            // Same as rs_merkle, an odd node is promoted as is
+            None => *left,
+        }
+    }
+}
+
+impl<H: DigestCompatibleHasher> DigestCompatibleHasher for Tagged<H> {
+    fn to_digest(hash: Self::Hash) -> Digest {
+        H::to_digest(hash)
+    }
+
+    fn from_digest(digest: &Digest) -> Result<Self::Hash> {
+        H::from_digest(digest)
+    }
+
+    fn zero_digest() -> Digest {
+        H::zero_digest()
+    }
+}
+
+/// Value inserted in the Merkle tree for a child of a directory. From
+/// version 2 it is `H(0x00 || type || len || name || digest [|| len || target])`.
+fn leaf_value<H>(leaf: &Leaf, version: u32) -> Result<H::Hash>
+where
+    H: DigestCompatibleHasher,
+{
+    if version == SCHEMA_V1 {
+        return H::from_digest(&leaf.hash);
+    }
+
+    let name = leaf.name.as_bytes();
+    let mut data = Vec::with_capacity(10 + name.len() + leaf.hash.as_ref().len());
+    data.push(LEAF_TAG);
+    data.push(match leaf.kind.unwrap_or(EntryKind::File) {
+        EntryKind::File => 0,
+        EntryKind::Dir => 1,
+        EntryKind::Symlink => 2,
+    });
+    data.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    data.extend_from_slice(name);
+    data.extend_from_slice(leaf.hash.as_ref());
+    // Followed links commit to their target too, so retargeting shows up
+    if let Some(target) = &leaf.target {
+        data.extend_from_slice(&(target.len() as u64).to_le_bytes());
+        data.extend_from_slice(target.as_bytes());
+    }
+    Ok(H::hash(&data))
+}
+
+fn leaf_values<H>(children: &[Leaf], version: u32) -> Result<Vec<H::Hash>>
+where
+    H: DigestCompatibleHasher,
+{
+    children
+        .iter()
+        .map(|c| leaf_value::<H>(c, version))
+        .collect()
+}
+
+fn tree_root<H>(children: &[Leaf], version: u32) -> Result<Digest>
+where
+    H: DigestCompatibleHasher,
+{
+    if children.is_empty() {
+        return Ok(empty_root::<H>());
+    }
+    let tree = MerkleTree::<H>::from_leaves(&leaf_values::<H>(children, version)?);
+    let root = tree.root().ok_or(Error::EmptyTree)?;
+    Ok(H::to_digest(root))
+}
+
+fn tree_proof<H>(children: &[Leaf], index: usize, version: u32) -> Result<Vec<Digest>>
+where
+    H: DigestCompatibleHasher,
+{
+    let tree = MerkleTree::<H>::from_leaves(&leaf_values::<H>(children, version)?);
+    let proof = tree.proof(&[index]);
+    Ok(proof
+        .proof_hashes()
+        .iter()
+        .map(|h| H::to_digest(*h))
+        .collect())
+}
+
+fn tree_proof_root<H>(proof: &[Digest], index: usize, total: usize, leaf: H::Hash) -> Result<Digest>
+where
+    H: DigestCompatibleHasher,
+{
+    let hashes = proof
+        .iter()
+        .map(H::from_digest)
+        .collect::<Result<Vec<_>>>()?;
+    let root = MerkleProof::<H>::new(hashes).root(&[index], &[leaf], total)?;
+    Ok(H::to_digest(root))
+}
+
+/// Root of an empty directory: the digest of the empty string. It cannot be
+/// mistaken for a leaf or an inner node, which always start with a tag byte.
+pub fn empty_root<H>() -> Digest
+where
+    H: DigestCompatibleHasher,
+{
+    H::to_digest(H::hash(&[]))
+}
+
+/// Root of the chunk digests of a file. Chunks go into the tree as
+/// `H(0x00 || digest)`, so a file cannot have the root of other chunks by
+/// holding the bytes of an inner node.
+pub fn chunk_root<H>(chunks: &[Digest]) -> Result<Digest>
+where
+    H: DigestCompatibleHasher,
+{
+    if chunks.is_empty() {
+        return Ok(empty_root::<H>());
+    }
+    let leaves: Vec<H::Hash> = chunks
+        .iter()
+        .map(|chunk| {
+            let mut data = Vec::with_capacity(1 + chunk.as_ref().len());
+            data.push(LEAF_TAG);
+            data.extend_from_slice(chunk.as_ref());
+            H::hash(&data)
+        })
+        .collect();
+    let tree = MerkleTree::<Tagged<H>>::from_leaves(&leaves);
+    let root = tree.root().ok_or(Error::EmptyTree)?;
+    Ok(H::to_digest(root))
+}
+
+/// Root hash of a directory given its sorted children
+pub fn merkle_root<H>(children: &[Leaf], version: u32) -> Result<Digest>
+where
+    H: DigestCompatibleHasher,
+{
+    if version == SCHEMA_V1 {
+        tree_root::<H>(children, version)
+    } else {
+        tree_root::<Tagged<H>>(children, version)
+    }
+}
+
+/// Sibling hashes needed to prove the child at `index`
+pub fn merkle_proof<H>(children: &[Leaf], index: usize, version: u32) -> Result<Vec<Digest>>
+where
+    H: DigestCompatibleHasher,
+{
+    if version == SCHEMA_V1 {
+        tree_proof::<H>(children, index, version)
+    } else {
+        tree_proof::<Tagged<H>>(children, index, version)
+    }
+}
+
+/// Recompute a directory root from one child and its proof
+pub fn merkle_proof_root<H>(
+    proof: &[Digest],
+    index: usize,
+    total: usize,
+    leaf: &Leaf,
+    version: u32,
+) -> Result<Digest>
+where
+    H: DigestCompatibleHasher,
+{
+    let leaf = leaf_value::<H>(leaf, version)?;
+    if version == SCHEMA_V1 {
+        tree_proof_root::<H>(proof, index, total, leaf)
+    } else {
+        tree_proof_root::<Tagged<H>>(proof, index, total, leaf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashers::sha256::Sha256Algorithm;
+
+    fn leaf(name: &str, content: &[u8], kind: EntryKind) -> Leaf {
+        Leaf {
+            name: name.to_string(),
+            hash: Sha256Algorithm::to_digest(Sha256Algorithm::hash(content)),
+            kind: Some(kind),
+            target: None,
+            stat: None,
+            chunks: None,
+        }
+    }
+
+    fn root(children: &[Leaf], version: u32) -> Digest {
+        merkle_root::<Sha256Algorithm>(children, version).unwrap()
+    }
+
+    fn children() -> Vec<Leaf> {
+        vec![
+            leaf("a.txt", b"first", EntryKind::File),
+            leaf("b.txt", b"second", EntryKind::File),
+        ]
+    }
+
+    #[test]
+    fn v2_root_commits_to_names() {
+        let original = root(&children(), SCHEMA_V2);
+
+        let mut renamed = children();
+        renamed[1].name = "c.txt".into();
+        assert_ne!(root(&renamed, SCHEMA_V2), original);
+
+        // Contents swapped between the two names
+        let mut swapped = children();
+        let hash = swapped[0].hash;
+        swapped[0].hash = swapped[1].hash;
+        swapped[1].hash = hash;
+        assert_ne!(root(&swapped, SCHEMA_V2), original);
+
+        // Version 1 only sees the digests
+        assert_eq!(root(&renamed, SCHEMA_V1), root(&children(), SCHEMA_V1));
+    }
+
+    #[test]
+    fn v2_root_commits_to_types_and_targets() {
+        let original = root(&children(), SCHEMA_V2);
+
+        for kind in [EntryKind::Dir, EntryKind::Symlink] {
+            let mut retyped = children();
+            retyped[0].kind = Some(kind);
+            assert_ne!(root(&retyped, SCHEMA_V2), original);
+        }
+
+        let mut linked = children();
+        linked[0].target = Some("elsewhere".into());
+        let mut relinked = children();
+        relinked[0].target = Some("somewhere".into());
+        assert_ne!(root(&linked, SCHEMA_V2), original);
+        assert_ne!(root(&linked, SCHEMA_V2), root(&relinked, SCHEMA_V2));
+    }
+
+    #[test]
+    fn v2_leaves_are_not_raw_digests() {
+        // A single child is still hashed with its name, unlike in version 1
+        let single = [leaf("a.txt", b"first", EntryKind::File)];
+        assert_eq!(root(&single, SCHEMA_V1), single[0].hash);
+        assert_ne!(root(&single, SCHEMA_V2), single[0].hash);
+        assert_eq!(root(&[], SCHEMA_V2), empty_root::<Sha256Algorithm>());
+    }
+
+    #[test]
+    fn chunk_leaves_are_not_raw_digests() {
+        let digest = |data: &[u8]| Sha256Algorithm::to_digest(Sha256Algorithm::hash(data));
+        let chunks = [digest(b"first chunk"), digest(b"second chunk")];
+        let root = chunk_root::<Sha256Algorithm>(&chunks).unwrap();
+
+        // A chunk holding the inner node of the two chunks above
+        let mut node = vec![NODE_TAG];
+        node.extend_from_slice(chunks[0].as_ref());
+        node.extend_from_slice(chunks[1].as_ref());
+        assert_ne!(digest(&node), root);
+        assert_ne!(
+            chunk_root::<Sha256Algorithm>(&[digest(&node)]).unwrap(),
+            root
+        );
+
+        assert_ne!(
+            chunk_root::<Sha256Algorithm>(&chunks[..1]).unwrap(),
+            chunks[0]
+        );
+        assert_eq!(
+            chunk_root::<Sha256Algorithm>(&[]).unwrap(),
+            empty_root::<Sha256Algorithm>()
+        );
+    }
+
+    #[test]
+    fn proofs_fold_back_to_the_root() {
+        let children: Vec<Leaf> = (0..5)
+            .map(|i| leaf(&format!("{i}.bin"), &[i], EntryKind::File))
+            .collect();
+        for version in [SCHEMA_V1, SCHEMA_V2] {
+            let expected = root(&children, version);
+            for (index, child) in children.iter().enumerate() {
+                let proof = merkle_proof::<Sha256Algorithm>(&children, index, version).unwrap();
+                let folded = merkle_proof_root::<Sha256Algorithm>(
+                    &proof,
+                    index,
+                    children.len(),
+                    child,
+                    version,
+                )
+                .unwrap();
+                assert_eq!(folded, expected);
+            }
+        }
+    }
+}
@@ -1,4 +1,13 @@
-pub(crate) mod blake3;
-pub(crate) mod md5;
-pub(crate) mod sha256;
-pub(crate) mod utils;
+pub mod blake3;
+pub mod chunking;
+pub mod crc32c;
+pub mod md5;
+pub mod merkle;
+pub mod registry;
+pub mod sampling;
+pub mod sha1;
+pub mod sha256;
+pub mod sha3_256;
+pub mod sha512;
+pub mod utils;
+pub mod xxh3;
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::io::{self, Read, Seek};
+use std::sync::RwLock;
+
+use crate::error::{Error, Result};
+use crate::hashers::blake3::Blake3Algorithm;
+use crate::hashers::chunking::{Chunking, FileHash, chunks_with};
+use crate::hashers::crc32c::Crc32cAlgorithm;
+use crate::hashers::md5::Md5Algorithm;
+use crate::hashers::merkle::{empty_root, merkle_proof, merkle_proof_root, merkle_root};
+use crate::hashers::sampling::{Sampling, sampled_with};
+use crate::hashers::sha1::Sha1Algorithm;
+use crate::hashers::sha3_256::Sha3_256Algorithm;
+use crate::hashers::sha256::Sha256Algorithm;
+use crate::hashers::sha512::Sha512Algorithm;
+use crate::hashers::utils::{Digest, DigestCompatibleHasher, HashMethod};
+use crate::hashers::xxh3::Xxh3Algorithm;
+use crate::utils::Leaf;
+
+/// A hash algorithm every command can use: the Merkle node hasher along
+/// with the streaming hasher of file contents. Implement it and `register`
+/// the type to build and diff with it.
+pub trait HashAlgorithm: DigestCompatibleHasher + 'static {
+    /// Name given to `method` and recorded in the manifests
+    const NAME: &'static str;
+    /// Length of the digests in bytes, one of `Digest::WIDTHS`
+    const DIGEST_LEN: usize;
+    /// False for checksums that only detect accidental changes, inclusion
+    /// proofs refuse them
+    const CRYPTOGRAPHIC: bool = true;
+
+    /// Digest of the content of `reader`, only of its first `bytes_to_hash`
+    /// bytes unless 0
+    fn hash_reader(reader: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest>;
+
+    /// Bytes hashed before the content of a file of `size` bytes, like the
+    /// `blob <size>` header of git. None by default.
+    fn content_header(_size: u64) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// `Read + Seek` trait object, for sampled hashing
+pub(crate) trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Directory root from one child and its proof: the sibling hashes, the
+/// index of the child, the number of children and the schema version
+type ProofRootFn = fn(&[Digest], usize, usize, &Leaf, u32) -> Result<Digest>;
+
+/// A `HashAlgorithm` with the generic hashing and Merkle tree code
+/// instantiated for it, so that it can be picked at runtime from its name
+pub struct Algorithm {
+    name: &'static str,
+    digest_len: usize,
+    cryptographic: bool,
+    hash_bytes: fn(&[u8]) -> Digest,
+    zero_digest: fn() -> Digest,
+    hash_reader: fn(&mut dyn Read, u64, usize) -> Result<Digest>,
+    content_header: fn(u64) -> Vec<u8>,
+    chunks: fn(&mut dyn Read, &Chunking) -> Result<FileHash>,
+    empty_root: fn() -> Digest,
+    merkle_root: fn(&[Leaf], u32) -> Result<Digest>,
+    merkle_proof: fn(&[Leaf], usize, u32) -> Result<Vec<Digest>>,
+    merkle_proof_root: ProofRootFn,
+}
+
+impl Algorithm {
+    pub const fn of<H: HashAlgorithm>() -> Self {
+        Algorithm {
+            name: H::NAME,
+            digest_len: H::DIGEST_LEN,
+            cryptographic: H::CRYPTOGRAPHIC,
+            hash_bytes: bytes_with::<H>,
+            zero_digest: H::zero_digest,
+            hash_reader: read_with::<H>,
+            content_header: H::content_header,
+            chunks: chunks_with::<H>,
+            empty_root: empty_root::<H>,
+            merkle_root: merkle_root::<H>,
+            merkle_proof: merkle_proof::<H>,
+            merkle_proof_root: merkle_proof_root::<H>,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn digest_len(&self) -> usize {
+        self.digest_len
+    }
+
+    pub fn is_cryptographic(&self) -> bool {
+        self.cryptographic
+    }
+
+    /// Digest of a byte string, like the target of a symlink
+    pub fn hash_bytes(&self, data: &[u8]) -> Digest {
+        (self.hash_bytes)(data)
+    }
+
+    /// Placeholder digest of the right length
+    pub(crate) fn zero_digest(&self) -> Digest {
+        (self.zero_digest)()
+    }
+
+    pub fn hash_reader(
+        &self,
+        reader: &mut dyn Read,
+        bytes_to_hash: u64,
+        buffer_size: usize,
+    ) -> Result<Digest> {
+        (self.hash_reader)(reader, bytes_to_hash, buffer_size)
+    }
+
+    /// Digest of a file of `size` bytes, header included, only of its first
+    /// `bytes_to_hash` bytes unless 0
+    pub fn hash_content(
+        &self,
+        reader: &mut dyn Read,
+        size: u64,
+        bytes_to_hash: u64,
+        buffer_size: usize,
+    ) -> Result<Digest> {
+        let len = match bytes_to_hash {
+            0 => size,
+            limit => size.min(limit),
+        };
+        let header = (self.content_header)(len);
+        if header.is_empty() {
+            return self.hash_reader(reader, bytes_to_hash, buffer_size);
+        }
+        // The header announces `len` bytes, no more are hashed
+        let mut content = io::Cursor::new(header).chain(reader.take(len));
+        self.hash_reader(&mut content, 0, buffer_size)
+    }
+
+    pub(crate) fn hash_chunks(
+        &self,
+        reader: &mut dyn Read,
+        chunking: &Chunking,
+    ) -> Result<FileHash> {
+        (self.chunks)(reader, chunking)
+    }
+
+    pub(crate) fn hash_sampled(
+        &self,
+        reader: &mut dyn ReadSeek,
+        size: u64,
+        sampling: &Sampling,
+    ) -> Result<Digest> {
+        sampled_with(reader, size, sampling, self)
+    }
+
+    /// Root of an empty directory
+    pub(crate) fn empty_root(&self) -> Digest {
+        (self.empty_root)()
+    }
+
+    /// Root hash of a directory given its sorted children
+    pub fn merkle_root(&self, children: &[Leaf], version: u32) -> Result<Digest> {
+        (self.merkle_root)(children, version)
+    }
+
+    /// Sibling hashes needed to prove the child at `index`
+    pub fn merkle_proof(
+        &self,
+        children: &[Leaf],
+        index: usize,
+        version: u32,
+    ) -> Result<Vec<Digest>> {
+        (self.merkle_proof)(children, index, version)
+    }
+
+    /// Recompute a directory root from one child and its proof
+    pub fn merkle_proof_root(
+        &self,
+        proof: &[Digest],
+        index: usize,
+        total: usize,
+        leaf: &Leaf,
+        version: u32,
+    ) -> Result<Digest> {
+        (self.merkle_proof_root)(proof, index, total, leaf, version)
+    }
+}
+
+impl fmt::Debug for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Algorithm")
+            .field("name", &self.name)
+            .field("digest_len", &self.digest_len)
+            .field("cryptographic", &self.cryptographic)
+            .finish()
+    }
+}
+
+fn bytes_with<H: HashAlgorithm>(data: &[u8]) -> Digest {
+    H::to_digest(H::hash(data))
+}
+
+fn read_with<H: HashAlgorithm>(
+    reader: &mut dyn Read,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+) -> Result<Digest> {
+    H::hash_reader(reader, bytes_to_hash, buffer_size)
+}
+
+pub(crate) static MD5: Algorithm = Algorithm::of::<Md5Algorithm>();
+pub(crate) static BLAKE3: Algorithm = Algorithm::of::<Blake3Algorithm>();
+pub(crate) static SHA256: Algorithm = Algorithm::of::<Sha256Algorithm>();
+pub(crate) static SHA512: Algorithm = Algorithm::of::<Sha512Algorithm>();
+pub(crate) static SHA3_256: Algorithm = Algorithm::of::<Sha3_256Algorithm>();
+pub(crate) static SHA1: Algorithm = Algorithm::of::<Sha1Algorithm>();
+pub(crate) static XXH3: Algorithm = Algorithm::of::<Xxh3Algorithm>();
+pub(crate) static CRC32C: Algorithm = Algorithm::of::<Crc32cAlgorithm>();
+
+/// Algorithms registered by library users, leaked to live as long as the
+/// process like the built-in ones
+static REGISTERED: RwLock<Vec<&'static Algorithm>> = RwLock::new(Vec::new());
+
+/// Make `H` available to every command under `H::NAME`, returns the method
+/// to pass to the build options. Names are case insensitive and cannot be
+/// registered twice.
+pub fn register<H: HashAlgorithm>() -> Result<HashMethod> {
+    if !Digest::WIDTHS.contains(&H::DIGEST_LEN) {
+        return Err(Error::InvalidDigestLength(H::DIGEST_LEN));
+    }
+    let mut registered = REGISTERED.write().expect("hasher registry poisoned");
+    let taken = HashMethod::BUILTIN
+        .iter()
+        .map(HashMethod::algorithm)
+        .chain(registered.iter().copied())
+        .any(|a| a.name.eq_ignore_ascii_case(H::NAME));
+    if taken {
+        return Err(Error::DuplicateHashMethod(H::NAME.to_string()));
+    }
+    let algorithm: &'static Algorithm = Box::leak(Box::new(Algorithm::of::<H>()));
+    registered.push(algorithm);
+    Ok(HashMethod::Custom(algorithm))
+}
+
+/// The registered algorithm named `name`, ignoring case
+pub(crate) fn lookup(name: &str) -> Option<&'static Algorithm> {
+    REGISTERED
+        .read()
+        .expect("hasher registry poisoned")
+        .iter()
+        .find(|a| a.name.eq_ignore_ascii_case(name))
+        .copied()
+}
@@ -0,0 +1,288 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::hashers::registry::{Algorithm, ReadSeek};
+use crate::hashers::utils::{Digest, HashMethod};
+
+/// Read size when hashing the windows
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Windows hashed by a sampled build, recorded in every node. A file is
+/// hashed from its size, its first and last `window_size` bytes and
+/// `windows` evenly spaced windows in between.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampling {
+    pub window_size: u64,
+    pub windows: u32,
+}
+
+impl Sampling {
+    pub const DEFAULT_WINDOW_SIZE: u64 = 64 * 1024;
+    pub const DEFAULT_WINDOWS: u32 = 16;
+
+    pub fn new(window_size: u64, windows: u32) -> Result<Self> {
+        if window_size == 0 {
+            return Err(Error::InvalidSampling(
+                "the window size must be at least 1 byte".to_string(),
+            ));
+        }
+        Ok(Sampling {
+            window_size,
+            windows,
+        })
+    }
+
+    /// Files up to this size are hashed whole, the windows would overlap
+    pub fn threshold(&self) -> u64 {
+        self.window_size.saturating_mul(u64::from(self.windows) + 2)
+    }
+
+    /// Start of every window of a file of `size` bytes, head and tail
+    /// included, in increasing order
+    fn offsets(&self, size: u64) -> impl Iterator<Item = u64> + '_ {
+        let last = u128::from(size - self.window_size);
+        let steps = u128::from(self.windows) + 1;
+        (0..=steps).map(move |j| (last * j / steps) as u64)
+    }
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling {
+            window_size: Sampling::DEFAULT_WINDOW_SIZE,
+            windows: Sampling::DEFAULT_WINDOWS,
+        }
+    }
+}
+
+impl fmt::Display for Sampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "head, tail and {} windows of {} bytes",
+            self.windows, self.window_size
+        )
+    }
+}
+
+/// Only moves forward by reading and dropping bytes, for streams like
+/// archive members
+pub struct Forward<R> {
+    inner: R,
+    pos: u64,
+}
+
+impl<R: Read> Forward<R> {
+    pub fn new(inner: R) -> Self {
+        Forward { inner, pos: 0 }
+    }
+}
+
+impl<R: Read> Read for Forward<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Seek for Forward<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(target) if target >= self.pos => target,
+            SeekFrom::Current(delta) if delta >= 0 => self.pos + delta as u64,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "cannot seek backwards in a stream",
+                ));
+            }
+        };
+        let skipped = io::copy(
+            &mut (&mut self.inner).take(target - self.pos),
+            &mut io::sink(),
+        )?;
+        self.pos += skipped;
+        Ok(self.pos)
+    }
+}
+
+/// Hash the sampled windows of `reader`, `size` bytes long
+pub fn hash_sampled(
+    mut reader: impl Read + Seek,
+    size: u64,
+    method: &HashMethod,
+    sampling: &Sampling,
+) -> Result<Digest> {
+    method.algorithm().hash_sampled(&mut reader, size, sampling)
+}
+
+/// Same as `hash_sampled` for a file on disk
+pub fn hash_file_sampled(path: &Path, method: &HashMethod, sampling: &Sampling) -> Result<Digest> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    hash_sampled(file, size, method, sampling)
+}
+
+/// The windows of a sampled file read one after the other, seeking to each
+struct Windows<'a, I> {
+    reader: &'a mut dyn ReadSeek,
+    offsets: I,
+    window_size: u64,
+    /// Bytes left in the current window
+    left: u64,
+}
+
+impl<I: Iterator<Item = u64>> Read for Windows<'_, I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.left == 0 {
+            let Some(offset) = self.offsets.next() else {
+                return Ok(0);
+            };
+            self.reader.seek(SeekFrom::Start(offset))?;
+            self.left = self.window_size;
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(self.left).unwrap_or(usize::MAX));
+        let n = self.reader.read(&mut buf[..len])?;
+        if n == 0 && len > 0 {
+            // The file is shorter than its size
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.left -= n as u64;
+        Ok(n)
+    }
+}
+
+pub(crate) fn sampled_with(
+    reader: &mut dyn ReadSeek,
+    size: u64,
+    sampling: &Sampling,
+    algorithm: &Algorithm,
+) -> Result<Digest> {
+    // The size is always hashed, appending to a file changes its digest even
+    // when the windows do not see it
+    let header = io::Cursor::new(size.to_le_bytes());
+    if size <= sampling.threshold() {
+        algorithm.hash_reader(&mut header.chain(reader), 0, BUFFER_SIZE)
+    } else {
+        let windows = Windows {
+            reader,
+            offsets: sampling.offsets(size),
+            window_size: sampling.window_size,
+            left: 0,
+        };
+        algorithm.hash_reader(&mut header.chain(windows), 0, BUFFER_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Head, tail and 2 windows of 16 bytes, files over 64 bytes are sampled
+    fn sampling() -> Sampling {
+        Sampling::new(16, 2).unwrap()
+    }
+
+    fn data() -> Vec<u8> {
+        (0..1000u32).map(|i| (i * 7 % 256) as u8).collect()
+    }
+
+    fn digest(data: &[u8]) -> Digest {
+        let size = data.len() as u64;
+        hash_sampled(Cursor::new(data), size, &HashMethod::Sha256, &sampling()).unwrap()
+    }
+
+    #[test]
+    fn streams_hash_like_seekable_readers() {
+        let data = data();
+        for data in [&data[..], &data[..40]] {
+            let streamed = hash_sampled(
+                Forward::new(data),
+                data.len() as u64,
+                &HashMethod::Sha256,
+                &sampling(),
+            )
+            .unwrap();
+            assert_eq!(streamed, digest(data));
+        }
+    }
+
+    #[test]
+    fn windows_are_head_tail_and_evenly_spaced() {
+        let offsets: Vec<u64> = sampling().offsets(1000).collect();
+        assert_eq!(offsets, [0, 328, 656, 984]);
+
+        // Only the size and the windows are hashed
+        let data = data();
+        let mut expected = 1000u64.to_le_bytes().to_vec();
+        for offset in offsets {
+            expected.extend_from_slice(&data[offset as usize..offset as usize + 16]);
+        }
+        assert_eq!(
+            digest(&data),
+            HashMethod::Sha256.algorithm().hash_bytes(&expected)
+        );
+    }
+
+    #[test]
+    fn size_changes_change_the_digest() {
+        let data = data();
+        let original = digest(&data);
+
+        let mut appended = data.clone();
+        appended.extend_from_slice(b"appended");
+        assert_ne!(digest(&appended), original);
+
+        assert_ne!(digest(&data[..999]), original);
+
+        // Same windows, the size alone tells the files apart
+        let mut longer = data.clone();
+        longer.push(0);
+        assert_ne!(digest(&longer[..1000]), digest(&longer));
+    }
+
+    #[test]
+    fn only_the_windows_are_compared() {
+        let data = data();
+        let original = digest(&data);
+
+        let mut in_window = data.clone();
+        in_window[330] ^= 1;
+        assert_ne!(digest(&in_window), original);
+
+        let mut between = data.clone();
+        between[500] ^= 1;
+        assert_eq!(digest(&between), original);
+
+        // Small files are hashed whole
+        let mut small = data[..64].to_vec();
+        let whole = digest(&small);
+        small[30] ^= 1;
+        assert_ne!(digest(&small), whole);
+    }
+
+    #[test]
+    fn short_reads_and_backward_seeks_fail() {
+        let data = data();
+        // The reader ends before the announced size
+        let short = hash_sampled(
+            Cursor::new(&data[..500]),
+            1000,
+            &HashMethod::Sha256,
+            &sampling(),
+        );
+        assert!(short.is_err());
+
+        let mut forward = Forward::new(&data[..]);
+        assert_eq!(forward.seek(SeekFrom::Start(100)).unwrap(), 100);
+        assert!(forward.seek(SeekFrom::Start(50)).is_err());
+    }
+}
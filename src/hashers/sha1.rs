@@ -0,0 +1,111 @@
+use openssl::hash::{Hasher, MessageDigest};
+use rs_merkle::Hasher as Mh;
+use std::io::{BufReader, Read};
+
+use crate::error::{Error, Result};
+use crate::hashers::registry::HashAlgorithm;
+use crate::hashers::utils::{Digest, DigestCompatibleHasher};
+
+/// SHA-1, the object hash of git. Files are hashed as git blobs, so their
+/// digests are the ids `git hash-object` gives them. Broken against
+/// collisions, only for interoperability with tools that need it.
+#[derive(Clone)]
+pub struct Sha1Algorithm {}
+
+impl Mh for Sha1Algorithm {
+    type Hash = [u8; 20];
+
+    fn hash(data: &[u8]) -> Self::Hash {
+        let digest = openssl::hash::hash(MessageDigest::sha1(), data)
+            .expect("OpenSSL should be available at runtime");
+        (*digest).try_into().expect("SHA-1 should be 20 bytes")
+    }
+}
+
+impl DigestCompatibleHasher for Sha1Algorithm {
+    fn to_digest(hash: Self::Hash) -> Digest {
+        Digest::D20(hash)
+    }
+
+    fn from_digest(digest: &Digest) -> Result<Self::Hash> {
+        match digest {
+            Digest::D20(arr) => Ok(*arr),
+            _ => Err(Error::DigestMismatch {
+                method: "sha1",
+                expected: 20,
+            }),
+        }
+    }
+
+    fn zero_digest() -> Digest {
+        Digest::D20([0u8; 20])
+    }
+}
+
+impl HashAlgorithm for Sha1Algorithm {
+    const NAME: &'static str = "sha1";
+    const DIGEST_LEN: usize = 20;
+    // Colliding files can be crafted, proofs must not rely on it
+    const CRYPTOGRAPHIC: bool = false;
+
+    fn hash_reader(reader: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
+        sha1_hash_reader(reader, bytes_to_hash, buffer_size)
+    }
+
+    fn content_header(size: u64) -> Vec<u8> {
+        format!("blob {size}\0").into_bytes()
+    }
+}
+
+pub fn sha1_hash_reader(file: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
+    let mut hasher = Hasher::new(MessageDigest::sha1())?;
+    if bytes_to_hash > 0 {
+        let taker = file.take(bytes_to_hash);
+        let mut reader = BufReader::with_capacity(buffer_size, taker);
+        std::io::copy(&mut reader, &mut hasher)?;
+    } else {
+        let mut reader = BufReader::with_capacity(buffer_size, file);
+        std::io::copy(&mut reader, &mut hasher)?;
+    }
+
+    let digest = hasher.finish()?;
+    let digest = (*digest)
+        .try_into()
+        .map_err(|_| Error::InvalidDigestLength(digest.len()))?;
+    Ok(Digest::D20(digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hashers::chunking::hash_content;
+    use crate::hashers::utils::HashMethod;
+
+    fn git_id(content: &[u8], bytes_to_hash: u64) -> String {
+        let hash = hash_content(
+            content,
+            content.len() as u64,
+            &HashMethod::Sha1,
+            bytes_to_hash,
+            8192,
+            None,
+            None,
+        )
+        .unwrap();
+        hex::encode(hash.digest)
+    }
+
+    #[test]
+    fn files_have_their_git_blob_id() {
+        // `printf ... | git hash-object --stdin`
+        assert_eq!(
+            git_id(b"123456789", 0),
+            "e2e107ac61ac259b87c544f6e7a4eb03422c6c21"
+        );
+        assert_eq!(git_id(b"", 0), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        // The blob of the bytes that are hashed
+        assert_eq!(
+            git_id(b"123456789", 4),
+            "274c0052dd5408f8ae2bc8440029ff67d79bc5c3"
+        );
+    }
+}
@@ -1,12 +1,9 @@
-use std::{
-    io::{BufReader, Read},
-    path::Path,
-};
+use std::io::{BufReader, Read};
 
-use anyhow::{Result, bail};
 use openssl::hash::{Hasher, MessageDigest};
-use std::fs::File;
 
+use crate::error::{Error, Result};
+use crate::hashers::registry::HashAlgorithm;
 use crate::hashers::utils::{Digest, DigestCompatibleHasher};
 pub use rs_merkle::algorithms::Sha256 as Sha256Algorithm;
 
@@ -17,7 +14,10 @@ impl DigestCompatibleHasher for Sha256Algorithm {
     fn from_digest(digest: &Digest) -> Result<Self::Hash> {
         match digest {
             Digest::D32(arr) => Ok(*arr),
-            _ => bail!("Expected 32-byte digest for Sha256"),
+            _ => Err(Error::DigestMismatch {
+                method: "sha256",
+                expected: 32,
+            }),
         }
     }
 
@@ -26,8 +26,20 @@ impl DigestCompatibleHasher for Sha256Algorithm {
     }
 }
 
-pub fn sha256_hash_file(path: &Path, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
-    let file = File::open(path)?;
+impl HashAlgorithm for Sha256Algorithm {
+    const NAME: &'static str = "sha256";
+    const DIGEST_LEN: usize = 32;
+
+    fn hash_reader(reader: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
+        sha256_hash_reader(reader, bytes_to_hash, buffer_size)
+    }
+}
+
+pub fn sha256_hash_reader(
+    file: impl Read,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+) -> Result<Digest> {
     let mut hasher = Hasher::new(MessageDigest::sha256())?;
     if bytes_to_hash > 0 {
         let taker = file.take(bytes_to_hash);
@@ -39,5 +51,8 @@ pub fn sha256_hash_file(path: &Path, bytes_to_hash: u64, buffer_size: usize) ->
     }
 
     let digest = hasher.finish()?;
-    Ok(Digest::D32((*digest).try_into()?))
+    let digest = (*digest)
+        .try_into()
+        .map_err(|_| Error::InvalidDigestLength(digest.len()))?;
+    Ok(Digest::D32(digest))
 }
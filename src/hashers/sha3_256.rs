@@ -0,0 +1,71 @@
+use openssl::hash::{Hasher, MessageDigest};
+use rs_merkle::Hasher as Mh;
+use std::io::{BufReader, Read};
+
+use crate::error::{Error, Result};
+use crate::hashers::registry::HashAlgorithm;
+use crate::hashers::utils::{Digest, DigestCompatibleHasher};
+
+#[derive(Clone)]
+pub struct Sha3_256Algorithm {}
+
+impl Mh for Sha3_256Algorithm {
+    type Hash = [u8; 32];
+
+    fn hash(data: &[u8]) -> Self::Hash {
+        let digest = openssl::hash::hash(MessageDigest::sha3_256(), data)
+            .expect("OpenSSL should be available at runtime");
+        (*digest).try_into().expect("SHA3-256 should be 32 bytes")
+    }
+}
+
+impl DigestCompatibleHasher for Sha3_256Algorithm {
+    fn to_digest(hash: Self::Hash) -> Digest {
+        Digest::D32(hash)
+    }
+
+    fn from_digest(digest: &Digest) -> Result<Self::Hash> {
+        match digest {
+            Digest::D32(arr) => Ok(*arr),
+            _ => Err(Error::DigestMismatch {
+                method: "sha3-256",
+                expected: 32,
+            }),
+        }
+    }
+
+    fn zero_digest() -> Digest {
+        Digest::D32([0u8; 32])
+    }
+}
+
+impl HashAlgorithm for Sha3_256Algorithm {
+    const NAME: &'static str = "sha3-256";
+    const DIGEST_LEN: usize = 32;
+
+    fn hash_reader(reader: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
+        sha3_256_hash_reader(reader, bytes_to_hash, buffer_size)
+    }
+}
+
+pub fn sha3_256_hash_reader(
+    file: impl Read,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+) -> Result<Digest> {
+    let mut hasher = Hasher::new(MessageDigest::sha3_256())?;
+    if bytes_to_hash > 0 {
+        let taker = file.take(bytes_to_hash);
+        let mut reader = BufReader::with_capacity(buffer_size, taker);
+        std::io::copy(&mut reader, &mut hasher)?;
+    } else {
+        let mut reader = BufReader::with_capacity(buffer_size, file);
+        std::io::copy(&mut reader, &mut hasher)?;
+    }
+
+    let digest = hasher.finish()?;
+    let digest = (*digest)
+        .try_into()
+        .map_err(|_| Error::InvalidDigestLength(digest.len()))?;
+    Ok(Digest::D32(digest))
+}
@@ -1,76 +1,195 @@
-use crate::hashers::{blake3::blake3_hash_file, md5::md5_hash_file, sha256::sha256_hash_file};
-use anyhow::{Ok, Result};
+use crate::error::{Error, Result};
+use crate::hashers::registry::{self, Algorithm};
 use clap::ValueEnum;
+use clap::builder::PossibleValue;
 use hex::{FromHex, decode_to_slice};
 use rs_merkle::Hasher;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs::File;
+use std::io::Read;
 use std::{fmt, path::Path};
 
-#[derive(Default, Clone, ValueEnum, Debug)]
+#[derive(Default, Clone, Debug)]
 pub enum HashMethod {
-    #[clap(alias = "md5")]
     #[default]
     Md5,
     Blake3,
     Sha256,
+    Sha512,
+    Sha3_256,
+    /// SHA-1 as used by git, for interoperability only
+    Sha1,
+    /// XXH3-128, not cryptographic
+    Xxh3,
+    /// CRC32C, not cryptographic
+    Crc32c,
+    /// Algorithm added with `registry::register`
+    Custom(&'static Algorithm),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Digest {
+    D4([u8; 4]),
     D16([u8; 16]),
+    D20([u8; 20]),
     D32([u8; 32]),
+    D64([u8; 64]),
+}
+
+impl Digest {
+    /// Supported digest lengths in bytes
+    pub const WIDTHS: [usize; 5] = [4, 16, 20, 32, 64];
+    /// Length of the widest digest, SHA-512
+    pub const MAX_LEN: usize = 64;
 }
 
 impl AsRef<[u8]> for Digest {
     fn as_ref(&self) -> &[u8] {
         match self {
+            Digest::D4(b) => b,
             Digest::D16(b) => b,
+            Digest::D20(b) => b,
             Digest::D32(b) => b,
+            Digest::D64(b) => b,
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Digest {
+    type Error = Error;
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        match bytes.len() {
+            4 => Ok(Digest::D4(bytes.try_into().expect("4 bytes"))),
+            16 => Ok(Digest::D16(bytes.try_into().expect("16 bytes"))),
+            20 => Ok(Digest::D20(bytes.try_into().expect("20 bytes"))),
+            32 => Ok(Digest::D32(bytes.try_into().expect("32 bytes"))),
+            64 => Ok(Digest::D64(bytes.try_into().expect("64 bytes"))),
+            n => Err(Error::InvalidDigestLength(n)),
         }
     }
 }
 
 impl FromHex for Digest {
-    type Error = anyhow::Error;
+    type Error = Error;
     fn from_hex<T: AsRef<[u8]>>(hex: T) -> Result<Self, Self::Error> {
-        let hex_bytes = hex.as_ref();
         // Divide by 2 since each byte is 2 hex chars
-        match hex_bytes.len() / 2 {
-            // https://docs.rs/hex/latest/src/hex/lib.rs.html#206
-            16 => {
-                let mut out = [0u8; 16];
-                decode_to_slice(hex, &mut out as &mut [u8])?;
-                Ok(Digest::D16(out))
-            }
-            32 => {
-                let mut out = [0u8; 32];
-                decode_to_slice(hex, &mut out as &mut [u8])?;
-                Ok(Digest::D32(out))
-            }
-            _ => Err(anyhow::anyhow!(
-                "Invalid hex string length: {} bytes",
-                hex_bytes.len() / 2
-            )),
+        let len = hex.as_ref().len() / 2;
+        if len > Digest::MAX_LEN {
+            return Err(Error::InvalidDigestLength(len));
         }
+        // https://docs.rs/hex/latest/src/hex/lib.rs.html#206
+        let mut out = [0u8; Digest::MAX_LEN];
+        decode_to_slice(hex, &mut out[..len])?;
+        Digest::try_from(&out[..len])
+    }
+}
+
+/// Digests are written as hex strings in text formats, raw bytes otherwise
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            hex::serde::serialize(self, serializer)
+        } else {
+            serializer.serialize_bytes(self.as_ref())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            hex::serde::deserialize(deserializer)
+        } else {
+            deserializer.deserialize_bytes(DigestBytes)
+        }
+    }
+}
+
+struct DigestBytes;
+
+impl<'de> Visitor<'de> for DigestBytes {
+    type Value = Digest;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("4, 16, 20, 32 or 64 digest bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Digest, E> {
+        Digest::try_from(bytes).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Digest, A::Error> {
+        let mut bytes = Vec::with_capacity(32);
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Digest::try_from(bytes.as_slice()).map_err(de::Error::custom)
     }
 }
 
 impl HashMethod {
-    pub fn as_str(&self) -> &'static str {
+    /// Methods available without registering anything
+    pub const BUILTIN: [HashMethod; 8] = [
+        HashMethod::Md5,
+        HashMethod::Blake3,
+        HashMethod::Sha256,
+        HashMethod::Sha512,
+        HashMethod::Sha3_256,
+        HashMethod::Sha1,
+        HashMethod::Xxh3,
+        HashMethod::Crc32c,
+    ];
+
+    /// Parse the method name stored in a manifest, built-in or registered
+    pub fn from_name(name: &str) -> Result<Self> {
+        Self::BUILTIN
+            .into_iter()
+            .find(|m| m.as_str().eq_ignore_ascii_case(name))
+            .or_else(|| registry::lookup(name).map(HashMethod::Custom))
+            .ok_or_else(|| Error::UnknownHashMethod(name.to_string()))
+    }
+
+    /// Hashers and generic code of the method
+    pub fn algorithm(&self) -> &'static Algorithm {
         match self {
-            HashMethod::Md5 => "md5",
-            HashMethod::Blake3 => "blake3",
-            HashMethod::Sha256 => "sha256",
+            HashMethod::Md5 => &registry::MD5,
+            HashMethod::Blake3 => &registry::BLAKE3,
+            HashMethod::Sha256 => &registry::SHA256,
+            HashMethod::Sha512 => &registry::SHA512,
+            HashMethod::Sha3_256 => &registry::SHA3_256,
+            HashMethod::Sha1 => &registry::SHA1,
+            HashMethod::Xxh3 => &registry::XXH3,
+            HashMethod::Crc32c => &registry::CRC32C,
+            HashMethod::Custom(algorithm) => algorithm,
         }
     }
+
+    pub fn as_str(&self) -> &'static str {
+        self.algorithm().name()
+    }
+
+    /// False for checksums that only detect accidental changes, anyone can
+    /// forge a collision. Inclusion proofs refuse them.
+    pub fn is_cryptographic(&self) -> bool {
+        self.algorithm().is_cryptographic()
+    }
+}
+
+/// The CLI offers the built-in methods
+impl ValueEnum for HashMethod {
+    fn value_variants<'a>() -> &'a [Self] {
+        &Self::BUILTIN
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(self.as_str()))
+    }
 }
 
 impl fmt::Display for HashMethod {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(match self {
-            HashMethod::Md5 => "md5",
-            HashMethod::Blake3 => "blake3",
-            HashMethod::Sha256 => "sha256",
-        })
+        f.write_str(self.as_str())
     }
 }
 
@@ -80,11 +199,23 @@ pub fn hash_file(
     bytes_to_hash: u64,
     buffer_size: usize,
 ) -> Result<Digest> {
-    match method {
-        HashMethod::Md5 => Ok(md5_hash_file(path, bytes_to_hash, buffer_size)?),
-        HashMethod::Blake3 => Ok(blake3_hash_file(path, bytes_to_hash, buffer_size)?),
-        HashMethod::Sha256 => Ok(sha256_hash_file(path, bytes_to_hash, buffer_size)?),
-    }
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    hash_reader(file, size, method, bytes_to_hash, buffer_size)
+}
+
+/// Same as `hash_file` for content that is not a file on disk, like an
+/// archive member, `size` bytes long
+pub fn hash_reader(
+    mut reader: impl Read,
+    size: u64,
+    method: &HashMethod,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+) -> Result<Digest> {
+    method
+        .algorithm()
+        .hash_content(&mut reader, size, bytes_to_hash, buffer_size)
 }
 
 pub trait DigestCompatibleHasher: Hasher {
@@ -0,0 +1,82 @@
+use rs_merkle::Hasher as Mh;
+use std::io::{self, BufReader, Read, Write};
+use xxhash_rust::xxh3::{Xxh3, xxh3_128};
+
+use crate::error::{Error, Result};
+use crate::hashers::registry::HashAlgorithm;
+use crate::hashers::utils::{Digest, DigestCompatibleHasher};
+
+/// XXH3 with 128-bit output. Not cryptographic: fast change detection on
+/// trusted storage only.
+#[derive(Clone)]
+pub struct Xxh3Algorithm {}
+
+impl Mh for Xxh3Algorithm {
+    type Hash = [u8; 16];
+
+    fn hash(data: &[u8]) -> Self::Hash {
+        // Big endian, as printed by xxhsum
+        xxh3_128(data).to_be_bytes()
+    }
+}
+
+impl DigestCompatibleHasher for Xxh3Algorithm {
+    fn to_digest(hash: Self::Hash) -> Digest {
+        Digest::D16(hash)
+    }
+
+    fn from_digest(digest: &Digest) -> Result<Self::Hash> {
+        match digest {
+            Digest::D16(arr) => Ok(*arr),
+            _ => Err(Error::DigestMismatch {
+                method: "xxh3",
+                expected: 16,
+            }),
+        }
+    }
+
+    fn zero_digest() -> Digest {
+        Digest::D16([0u8; 16])
+    }
+}
+
+/// Lets `io::copy` feed the streaming hasher
+struct Xxh3Writer(Xxh3);
+
+impl Write for Xxh3Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HashAlgorithm for Xxh3Algorithm {
+    const NAME: &'static str = "xxh3";
+    const DIGEST_LEN: usize = 16;
+    const CRYPTOGRAPHIC: bool = false;
+
+    fn hash_reader(reader: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
+        xxh3_hash_reader(reader, bytes_to_hash, buffer_size)
+    }
+}
+
+pub fn xxh3_hash_reader(file: impl Read, bytes_to_hash: u64, buffer_size: usize) -> Result<Digest> {
+    let mut hasher = Xxh3Writer(Xxh3::new());
+
+    if bytes_to_hash > 0 {
+        // Hash only up to bytes_to_hash
+        // Read at most bytes_to_hash bytes
+        let taker = file.take(bytes_to_hash);
+        let mut reader = BufReader::with_capacity(buffer_size, taker);
+        std::io::copy(&mut reader, &mut hasher)?;
+    } else {
+        let mut reader = BufReader::with_capacity(buffer_size, file);
+        std::io::copy(&mut reader, &mut hasher)?;
+    }
+
+    Ok(Digest::D16(hasher.0.digest128().to_be_bytes()))
+}
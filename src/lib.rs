@@ -0,0 +1,45 @@
+//! Hash and compare datasets using Merkle trees.
+//!
+//! ```no_run
+//! use rush::{DatasetHasher, HashMethod};
+//!
+//! let output = DatasetHasher::new("./my_dataset")
+//!     .method(HashMethod::Blake3)
+//!     .num_workers(8)
+//!     .build()?;
+//! println!("{}", hex::encode(output.root));
+//!
+//! if let Some(diff) = rush::diff("./dataset_v1".as_ref(), "./dataset_v2".as_ref())? {
+//!     println!("{:?}", diff.changed);
+//! }
+//! # Ok::<(), rush::Error>(())
+//! ```
+mod archive;
+// The CLI lives in the `rush` binary, the library API is re-exported below
+#[doc(hidden)]
+pub mod commands;
+mod dataset;
+pub mod error;
+pub mod filter;
+pub mod format;
+pub mod hashers;
+#[cfg(feature = "python")]
+mod python;
+#[doc(hidden)]
+pub mod utils;
+
+pub use commands::diff::{
+    ByteRange, Diff, DiffEntry, DiffFormat, DiffMove, DiffOptions, diff, diff_manifests, diff_with,
+};
+pub use commands::prove::{Proof, ProofLevel, prove};
+pub use dataset::DatasetHasher;
+pub use error::{Error, Result};
+pub use hashers::registry::{HashAlgorithm, register};
+pub use hashers::utils::{Digest, DigestCompatibleHasher, HashMethod, hash_file};
+/// The Merkle node hasher trait, from the `rs_merkle` version `rush` is
+/// built with
+pub use rs_merkle::Hasher;
+pub use utils::{
+    BuildOutput, DiskManifest, EntryKind, FailedFile, FileManifest, FileStat, Leaf, ManifestLayout,
+    ManifestReader, MemoryManifest, Node, SymlinkMode, open_manifest,
+};
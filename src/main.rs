@@ -1,23 +1,26 @@
 mod cli;
-mod commands;
-mod hashers;
-mod utils;
-use anyhow::Result;
 use clap::Parser;
+use rush::{Result, commands, hashers, utils};
 use std::process::ExitCode;
 
 fn main() -> ExitCode {
-    if let Err(err) = rush() {
-        eprintln!("error:{:#}", err);
-        ExitCode::FAILURE
-    } else {
-        ExitCode::SUCCESS
+    let cli = cli::Cli::parse();
+    // Same as GNU diff, 1 means that the datasets differ
+    let failure = match cli.command {
+        cli::Command::Diff { .. } => ExitCode::from(2),
+        _ => ExitCode::FAILURE,
+    };
+
+    match rush(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error:{}", err);
+            failure
+        }
     }
 }
 
-fn rush() -> Result<()> {
-    let cli = cli::Cli::parse();
-
+fn rush(cli: cli::Cli) -> Result<ExitCode> {
     match cli.command {
         // Rush commands
         cli::Command::Build {
@@ -26,9 +29,101 @@ fn rush() -> Result<()> {
             bytes_to_hash,
             buffer_size,
             num_workers,
-        } => commands::build::invoke(&path, &method, bytes_to_hash, buffer_size, num_workers)?,
+            incremental,
+            keep_going,
+            chunking,
+            chunk_size,
+            sample,
+            sample_window,
+            sample_count,
+            patterns,
+            symlinks,
+            manifest_dir,
+            manifest_layout,
+            manifest_format,
+        } => {
+            let opts = utils::BuildOptions {
+                method,
+                bytes_to_hash,
+                buffer_size,
+                num_workers,
+                store: true,
+                incremental,
+                keep_going,
+                chunking: chunking
+                    .then(|| hashers::chunking::Chunking::new(chunk_size))
+                    .transpose()?,
+                sampling: sample
+                    .then(|| hashers::sampling::Sampling::new(sample_window, sample_count))
+                    .transpose()?,
+                patterns: patterns.into(),
+                symlinks,
+                schema_version: hashers::merkle::SCHEMA_V2,
+                manifest_dir,
+                layout: manifest_layout,
+                format: manifest_format,
+            };
+            commands::build::invoke(&path, &opts)?
+        }
+
+        cli::Command::Diff {
+            path1,
+            path2,
+            method,
+            bytes_to_hash,
+            num_workers,
+            patterns,
+            format,
+            manifest_dir,
+            quiet,
+            no_renames,
+        } => {
+            let opts = commands::diff::DiffOptions {
+                patterns: patterns.into(),
+                format,
+                quiet,
+                renames: !no_renames,
+                method,
+                bytes_to_hash,
+                num_workers,
+                manifest_dirs: manifest_dir,
+            };
+            if commands::diff::invoke(&path1, &path2, &opts)? {
+                return Ok(ExitCode::from(1));
+            }
+        }
+
+        cli::Command::Verify {
+            path,
+            manifest_dir,
+            buffer_size,
+            num_workers,
+            patterns,
+        } => commands::verify::invoke(
+            &path,
+            manifest_dir.as_deref(),
+            buffer_size,
+            num_workers,
+            &patterns.into(),
+        )?,
 
-        cli::Command::Diff { path1, path2 } => commands::diff::invoke(&path1, &path2)?,
+        cli::Command::Migrate { path, manifest_dir } => {
+            commands::migrate::invoke(&path, manifest_dir.as_deref())?
+        }
+
+        cli::Command::Prove {
+            dataset,
+            path,
+            manifest_dir,
+        } => commands::prove::invoke(&dataset, &path, manifest_dir.as_deref())?,
+
+        cli::Command::VerifyProof {
+            proof,
+            file,
+            root,
+            allow_v1,
+            buffer_size,
+        } => commands::prove::invoke_verify(&proof, &file, &root, allow_v1, buffer_size)?,
 
         cli::Command::Hash {
             path,
@@ -38,5 +133,5 @@ fn rush() -> Result<()> {
         } => commands::hash::invoke(&path, &method, bytes_to_hash, buffer_size)?,
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,180 @@
+//! Python bindings, built with `maturin build --features python`.
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::hashers::utils::HashMethod;
+use crate::utils::{MemoryManifest, manifest_root, open_manifest};
+use crate::{DatasetHasher, DiffEntry, hash_file as rush_hash_file};
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> PyErr {
+        match err {
+            Error::Io(e) => PyOSError::new_err(e.to_string()),
+            e => PyValueError::new_err(e.to_string()),
+        }
+    }
+}
+
+/// Difference between two datasets, paths are relative to the dataset roots
+#[pyclass(name = "Diff", frozen)]
+struct PyDiff {
+    /// Present only in the second dataset
+    #[pyo3(get)]
+    added: Vec<String>,
+    /// Present only in the first dataset
+    #[pyo3(get)]
+    removed: Vec<String>,
+    /// Present in both but different
+    #[pyo3(get)]
+    changed: Vec<String>,
+    /// Symlinks present in both with different targets
+    #[pyo3(get)]
+    retargeted: Vec<String>,
+    /// `(old, new)` paths of the entries moved with the same digest
+    #[pyo3(get)]
+    moved: Vec<(String, String)>,
+}
+
+#[pymethods]
+impl PyDiff {
+    fn __bool__(&self) -> bool {
+        !(self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.retargeted.is_empty()
+            && self.moved.is_empty())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Diff(added={:?}, removed={:?}, changed={:?}, retargeted={:?}, moved={:?})",
+            self.added, self.removed, self.changed, self.retargeted, self.moved
+        )
+    }
+}
+
+fn json_to_py<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    Ok(match value {
+        Value::Null => py.None().into_bound(py),
+        Value::Bool(b) => b.into_pyobject(py)?.to_owned().into_any(),
+        Value::Number(n) => match n.as_u64() {
+            Some(u) => u.into_pyobject(py)?.into_any(),
+            None => match n.as_i64() {
+                Some(i) => i.into_pyobject(py)?.into_any(),
+                None => n.as_f64().unwrap_or_default().into_pyobject(py)?.into_any(),
+            },
+        },
+        Value::String(s) => s.into_pyobject(py)?.into_any(),
+        Value::Array(items) => {
+            let items = items
+                .iter()
+                .map(|v| json_to_py(py, v))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new(py, items)?.into_any()
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (k, v) in map {
+                dict.set_item(k, json_to_py(py, v)?)?;
+            }
+            dict.into_any()
+        }
+    })
+}
+
+/// Manifest as a dict of node dicts keyed by directory, "" being the root
+fn manifest_to_py<'py>(py: Python<'py>, manifest: &MemoryManifest) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    for (rel, node) in &manifest.nodes {
+        let node = serde_json::to_value(node).map_err(Error::from)?;
+        dict.set_item(rel.to_string_lossy(), json_to_py(py, &node)?)?;
+    }
+    Ok(dict)
+}
+
+/// Build the Merkle tree of a dataset, returns `(root_hex, manifest)`
+#[pyfunction]
+#[allow(clippy::too_many_arguments)] // keyword arguments on the Python side
+#[pyo3(signature = (path, method="md5", workers=4, bytes_to_hash=0, buffer_size=8192, store=true, manifest_dir=None))]
+fn build<'py>(
+    py: Python<'py>,
+    path: PathBuf,
+    method: &str,
+    workers: usize,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+    store: bool,
+    manifest_dir: Option<PathBuf>,
+) -> PyResult<(String, Bound<'py, PyDict>)> {
+    let mut hasher = DatasetHasher::new(&path)
+        .method(HashMethod::from_name(method)?)
+        .num_workers(workers)
+        .bytes_to_hash(bytes_to_hash)
+        .buffer_size(buffer_size)
+        .store(store);
+    if let Some(dir) = &manifest_dir {
+        hasher = hasher.manifest_dir(dir);
+    }
+
+    let (root, manifest) = py.detach(|| -> Result<_, Error> {
+        let output = hasher.build()?;
+        let manifest = if store {
+            MemoryManifest::load(&*open_manifest(&manifest_root(
+                &path,
+                manifest_dir.as_deref(),
+            ))?)?
+        } else {
+            output.manifest
+        };
+        Ok((output.root, manifest))
+    })?;
+
+    Ok((hex::encode(root), manifest_to_py(py, &manifest)?))
+}
+
+fn paths(entries: Vec<DiffEntry>) -> Vec<String> {
+    entries.into_iter().map(|e| e.path).collect()
+}
+
+/// Compare two datasets, built or not, returns None when they are identical
+#[pyfunction]
+fn diff(py: Python<'_>, lhs: PathBuf, rhs: PathBuf) -> PyResult<Option<PyDiff>> {
+    let diff = py.detach(|| crate::diff(&lhs, &rhs))?;
+    Ok(diff.map(|d| PyDiff {
+        added: paths(d.added),
+        removed: paths(d.removed),
+        changed: paths(d.changed),
+        retargeted: paths(d.retargeted),
+        moved: d.moved.into_iter().map(|m| (m.from, m.to)).collect(),
+    }))
+}
+
+/// Hash a single file, returns the hex digest
+#[pyfunction]
+#[pyo3(signature = (path, method="md5", bytes_to_hash=0, buffer_size=8192))]
+fn hash_file(
+    py: Python<'_>,
+    path: PathBuf,
+    method: &str,
+    bytes_to_hash: u64,
+    buffer_size: usize,
+) -> PyResult<String> {
+    let method = HashMethod::from_name(method)?;
+    let digest =
+        py.detach(|| rush_hash_file(Path::new(&path), &method, bytes_to_hash, buffer_size))?;
+    Ok(hex::encode(digest))
+}
+
+#[pymodule]
+#[pyo3(name = "rush")]
+fn rush_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyDiff>()?;
+    m.add_function(wrap_pyfunction!(build, m)?)?;
+    m.add_function(wrap_pyfunction!(diff, m)?)?;
+    m.add_function(wrap_pyfunction!(hash_file, m)?)?;
+    Ok(())
+}
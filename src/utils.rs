@@ -1,39 +1,289 @@
-use anyhow::Ok;
-use anyhow::Result;
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::BufReader;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::path::PathBuf;
 
-use crate::hashers::utils::Digest;
+use crate::error::{Error, Result};
+use crate::filter::Patterns;
+use crate::format::ManifestFormat;
+use crate::hashers::chunking::{Chunk, Chunking};
+use crate::hashers::merkle::{SCHEMA_LATEST, SCHEMA_V1, SCHEMA_V2};
+use crate::hashers::registry::Algorithm;
+use crate::hashers::sampling::Sampling;
+use crate::hashers::utils::{Digest, HashMethod};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// File attributes recorded at hashing time. An incremental build reuses the
+/// stored hash of a file whose stat tuple is unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub ino: u64,
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+}
+
+impl FileStat {
+    #[cfg(unix)]
+    pub fn from_metadata(meta: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        FileStat {
+            size: meta.size(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            ino: meta.ino(),
+            ctime: meta.ctime(),
+            ctime_nsec: meta.ctime_nsec(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(meta: &Metadata) -> Self {
+        // No inode or ctime here, size and mtime are the best we have
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        FileStat {
+            size: meta.len(),
+            mtime: mtime.as_secs() as i64,
+            mtime_nsec: mtime.subsec_nanos() as i64,
+            ino: 0,
+            ctime: 0,
+            ctime_nsec: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    /// A link hashed from its target path rather than followed
+    Symlink,
+}
+
+/// How symbolic links are handled during a build
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkMode {
+    /// Hash what the link points to. Dangling links and links looping back
+    /// to a parent directory are hashed from their target path.
+    #[default]
+    Follow,
+    /// Hash the target path of the link, never its content
+    HashTargetPath,
+    /// Leave links out of the tree
+    Skip,
+    /// Fail the build on the first link
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Leaf {
     pub name: String,
-    #[serde(with = "hex::serde")]
     pub hash: Digest,
+    /// Missing from version 1 manifests
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<EntryKind>,
+    /// Target of the symbolic link, followed or not
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Only set for files, directories are tracked through their own node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stat: Option<FileStat>,
+    /// Chunks of a file in a chunked build, `hash` is their Merkle root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Vec<Chunk>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Node {
+    /// Manifests written before versioning have no field and are version 1
+    #[serde(default = "legacy_schema_version")]
+    pub schema_version: u32,
     pub name: String,
     pub hash_method: String,
-    #[serde(with = "hex::serde")]
+    /// Set when `hash_method` is a checksum like xxh3 or crc32c, the tree
+    /// detects accidental changes but proves nothing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub non_cryptographic: bool,
     pub root_hash: Digest,
     pub children: Vec<Leaf>,
     pub bytes_to_hash: u64,
+    /// Chunk sizes of a chunked build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunking: Option<Chunking>,
+    /// Windows of a sampled build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<Sampling>,
+    /// `--exclude`/`--include` patterns of the build, root node only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patterns: Option<Patterns>,
+    /// Symlink mode of the build, root node only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlinks: Option<SymlinkMode>,
+    /// Canonical path of the dataset, root node only, when the manifest is
+    /// stored outside of it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dataset_root: Option<PathBuf>,
+}
+
+pub(crate) fn legacy_schema_version() -> u32 {
+    SCHEMA_V1
+}
+
+/// Only the schema version of a node. Decoded when a node cannot be, so a
+/// manifest written by a newer rush is reported as such rather than by
+/// whichever field changed.
+#[derive(Deserialize)]
+struct Versioned {
+    #[serde(default = "legacy_schema_version")]
+    schema_version: u32,
+}
+
+/// Reject the schema versions this build does not know
+pub(crate) fn check_schema(version: u32, path: &Path) -> Result<()> {
+    if (SCHEMA_V1..=SCHEMA_LATEST).contains(&version) {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedSchema {
+            path: path.to_path_buf(),
+            version,
+            latest: SCHEMA_LATEST,
+        })
+    }
+}
+
+/// Environment variable read when `--manifest-dir` is not given
+pub const MANIFEST_DIR_ENV: &str = "RUSH_MANIFEST_DIR";
+
+/// Folder holding the manifest of `dataset`: `manifest_dir` when given,
+/// `<dataset>/.rush` otherwise, or `<archive>.rush` next to an archive
+pub fn manifest_root(dataset: &Path, manifest_dir: Option<&Path>) -> PathBuf {
+    match manifest_dir {
+        Some(dir) => dir.to_path_buf(),
+        None if dataset.is_file() => {
+            let mut name = dataset.file_name().unwrap_or_default().to_os_string();
+            name.push(".rush");
+            dataset.with_file_name(name)
+        }
+        None => dataset.join(".rush"),
+    }
+}
+
+/// User facing options of a build
+pub struct BuildOptions {
+    pub method: HashMethod,
+    pub bytes_to_hash: u64,
+    pub buffer_size: usize,
+    pub num_workers: usize,
+    /// Write the nodes under `.rush`, otherwise keep them in memory
+    pub store: bool,
+    pub incremental: bool,
+    /// Leave unreadable files out of the tree instead of failing the build.
+    /// Archives refuse it.
+    pub keep_going: bool,
+    /// Applied on top of the `.rushignore` files
+    pub patterns: Patterns,
+    pub symlinks: SymlinkMode,
+    /// Manifest version to build, see `hashers::merkle`
+    pub schema_version: u32,
+    /// Store the manifest there instead of `<dataset>/.rush`
+    pub manifest_dir: Option<PathBuf>,
+    pub layout: ManifestLayout,
+    pub format: ManifestFormat,
+    /// Split files in content-defined chunks, see `hashers::chunking`
+    pub chunking: Option<Chunking>,
+    /// Hash files from sampled windows, see `hashers::sampling`. Cannot be
+    /// combined with `bytes_to_hash` or `chunking`.
+    pub sampling: Option<Sampling>,
+}
+
+impl BuildOptions {
+    /// Reject options that cannot be combined
+    pub fn check(&self) -> Result<()> {
+        if self.num_workers == 0 {
+            return Err(Error::NoWorkers);
+        }
+        if self.sampling.is_some() && (self.bytes_to_hash > 0 || self.chunking.is_some()) {
+            return Err(Error::InvalidSampling(
+                "sampling cannot be combined with bytes_to_hash or chunking".to_string(),
+            ));
+        }
+        Ok(())
+    }
 }
 
-pub struct BuildConfig {
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            method: HashMethod::default(),
+            bytes_to_hash: 0,
+            buffer_size: 8192,
+            num_workers: 4,
+            store: true,
+            incremental: false,
+            keep_going: false,
+            patterns: Patterns::default(),
+            symlinks: SymlinkMode::default(),
+            schema_version: SCHEMA_V2,
+            manifest_dir: None,
+            layout: ManifestLayout::default(),
+            format: ManifestFormat::default(),
+            chunking: None,
+            sampling: None,
+        }
+    }
+}
+
+/// A file a worker could not hash
+#[derive(Serialize, Debug)]
+pub struct FailedFile {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+pub struct BuildOutput {
+    pub root: Digest,
+    /// Empty when the nodes were stored on disk
+    pub manifest: MemoryManifest,
+    /// Only filled with `keep_going`, the build fails otherwise
+    pub failed: Vec<FailedFile>,
+}
+
+pub(crate) struct BuildConfig {
     pub dataset_root: PathBuf,
     pub rush_root: PathBuf,
-    pub method: &'static str,
+    /// Hashes the symlink targets and the directory roots
+    pub algorithm: &'static Algorithm,
+    pub non_cryptographic: bool,
     pub bytes_to_hash: u64,
+    pub chunking: Option<Chunking>,
+    pub sampling: Option<Sampling>,
     pub store: bool,
+    pub incremental: bool,
+    pub schema_version: u32,
+    pub patterns: Patterns,
+    pub symlinks: SymlinkMode,
+    /// Recorded in the root node when the manifest lives elsewhere
+    pub recorded_root: Option<PathBuf>,
+    /// Manifest of the previous build, read by incremental builds
+    pub previous: Option<Box<dyn ManifestReader>>,
+    /// The previous nodes are stored in the layout and format this build
+    /// writes, an unchanged one does not need to be written again
+    pub previous_stored: bool,
+    pub format: ManifestFormat,
 }
 
-pub fn rel_path_str(root: &Path, path: &Path) -> String {
+pub(crate) fn rel_path_str(root: &Path, path: &Path) -> String {
     if root == path {
         root.file_name()
             .map(|s| s.to_string_lossy().into_owned())
@@ -44,11 +294,482 @@ pub fn rel_path_str(root: &Path, path: &Path) -> String {
     }
 }
 
-pub fn node_from_file(path: &Path) -> Result<Node> {
-    // Open the file in read-only mode with buffer.
-    let reader = BufReader::new(File::open(path)?);
+/// Read a node file, JSON or CBOR
+pub(crate) fn node_from_file(path: &Path) -> Result<Node> {
+    let bytes = fs::read(path)?;
+    match ManifestFormat::decode::<Node>(&bytes) {
+        Ok(node) => {
+            check_schema(node.schema_version, path)?;
+            Ok(node)
+        }
+        Err(e) => {
+            if let Ok(v) = ManifestFormat::decode::<Versioned>(&bytes) {
+                check_schema(v.schema_version, path)?;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Write the node file of a manifest directory in `format`
+pub(crate) fn write_node_file(node: &Node, dir: &Path, format: ManifestFormat) -> Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let file_path = dir.join(format.node_file());
+    match format {
+        ManifestFormat::Json => {
+            let file = BufWriter::new(File::create(&file_path)?);
+            serde_json::to_writer_pretty(file, node)?;
+        }
+        ManifestFormat::Cbor => fs::write(&file_path, format.encode(node)?)?,
+    }
+    // The node file of another format would be read in place of this one
+    for stale in ManifestFormat::ALL {
+        if stale != format {
+            remove_stale(&dir.join(stale.node_file()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove a file left by a previous build, if any
+pub(crate) fn remove_stale(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)?,
+        _ => Ok(()),
+    }
+}
+
+/// The node file of a manifest directory, in whichever format it was written
+pub(crate) fn node_file(dir: &Path) -> Option<PathBuf> {
+    ManifestFormat::ALL
+        .iter()
+        .map(|f| dir.join(f.node_file()))
+        .find(|p| p.is_file())
+}
+
+/// Read access to a Merkle tree manifest, one node per directory. Paths are
+/// relative to the dataset root, the empty path being the root itself.
+pub trait ManifestReader {
+    fn node(&self, rel: &Path) -> Result<Cow<'_, Node>>;
+    fn is_dir(&self, rel: &Path) -> bool;
+}
 
-    // Deserialize JSON contents of the files as 'Node'
-    let node = serde_json::from_reader(reader)?;
-    Ok(node)
+/// Manifest stored on disk as a `.rush` folder
+pub struct DiskManifest {
+    pub root: PathBuf,
+}
+
+impl DiskManifest {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DiskManifest { root: root.into() }
+    }
+}
+
+impl ManifestReader for DiskManifest {
+    fn node(&self, rel: &Path) -> Result<Cow<'_, Node>> {
+        let dir = self.root.join(rel);
+        // Report the missing JSON file, like before there was a choice
+        let file = node_file(&dir).unwrap_or_else(|| dir.join(ManifestFormat::Json.node_file()));
+        Ok(Cow::Owned(node_from_file(&file)?))
+    }
+
+    fn is_dir(&self, rel: &Path) -> bool {
+        node_file(&self.root.join(rel)).is_some()
+    }
+}
+
+/// How a stored manifest is laid out in its folder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ManifestLayout {
+    /// One `merkle.json` per directory, mirroring the dataset
+    #[default]
+    Tree,
+    /// A single `manifest.ndjson` file with an offset index
+    File,
+}
+
+/// First bytes of a single-file manifest
+const MANIFEST_FILE_MAGIC: &[u8] = br#"{"format":"rush-manifest""#;
+/// `{"index_offset":"<20 digits>"}\n`, always the last line
+const TRAILER_LEN: u64 = 40;
+
+/// Header line of a single-file manifest, always JSON
+#[derive(Serialize, Deserialize)]
+struct FileHeader {
+    format: String,
+    version: u32,
+    /// Encoding of the directory records and of the index
+    #[serde(default)]
+    encoding: ManifestFormat,
+}
+
+/// One record per directory
+#[derive(Serialize, Deserialize)]
+struct FileNode<'a> {
+    dir: PathBuf,
+    node: Cow<'a, Node>,
+}
+
+#[derive(Deserialize)]
+struct VersionedRecord {
+    node: Versioned,
+}
+
+/// Offset and length of each directory record
+#[derive(Serialize, Deserialize)]
+struct FileIndex {
+    index: HashMap<PathBuf, (u64, u64)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileTrailer {
+    /// Zero padded so the trailer has a fixed length
+    index_offset: String,
+}
+
+/// Manifest stored as a single file: a header line, one record per
+/// directory, the index of the records and a fixed size trailer pointing to
+/// the index. Only the index and the requested nodes are read. With the JSON
+/// encoding every record is a line, making the file NDJSON.
+pub struct FileManifest {
+    path: PathBuf,
+    file: File,
+    encoding: ManifestFormat,
+    index: HashMap<PathBuf, (u64, u64)>,
+}
+
+impl FileManifest {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let mut reader = BufReader::new(&file);
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header: FileHeader = serde_json::from_str(&header)?;
+        if header.version != 1 {
+            return Err(Error::InvalidManifest(format!(
+                "{}: unknown single-file manifest version {}",
+                path.display(),
+                header.version
+            )));
+        }
+
+        let end = file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+        let mut trailer = Vec::new();
+        (&file).read_to_end(&mut trailer)?;
+        let trailer: FileTrailer = serde_json::from_slice(&trailer)?;
+        let offset: u64 = trailer
+            .index_offset
+            .parse()
+            .map_err(|_| Error::InvalidManifest(format!("{}: bad index offset", path.display())))?;
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut index = vec![0u8; end.saturating_sub(offset) as usize];
+        file.read_exact(&mut index)?;
+        let index: FileIndex = ManifestFormat::decode(&index)?;
+        Ok(FileManifest {
+            path: path.to_path_buf(),
+            file,
+            encoding: header.encoding,
+            index: index.index,
+        })
+    }
+
+    /// Encoding of the directory records
+    pub fn encoding(&self) -> ManifestFormat {
+        self.encoding
+    }
+
+    /// Write `manifest` at `path`, through a temporary file so readers never
+    /// see a partial manifest
+    pub fn write(manifest: &MemoryManifest, path: &Path, encoding: ManifestFormat) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        let header = FileHeader {
+            format: "rush-manifest".to_string(),
+            version: 1,
+            encoding,
+        };
+        let mut offset = write_record(&mut out, &header, ManifestFormat::Json)?;
+
+        // Sorted so that a subtree is mostly contiguous
+        let mut dirs: Vec<_> = manifest.nodes.keys().collect();
+        dirs.sort();
+        let mut index = HashMap::with_capacity(dirs.len());
+        for dir in dirs {
+            let record = FileNode {
+                dir: dir.clone(),
+                node: Cow::Borrowed(&manifest.nodes[dir]),
+            };
+            let len = write_record(&mut out, &record, encoding)?;
+            index.insert(dir.clone(), (offset, len));
+            offset += len;
+        }
+
+        write_record(&mut out, &FileIndex { index }, encoding)?;
+        let trailer = FileTrailer {
+            index_offset: format!("{:020}", offset),
+        };
+        write_record(&mut out, &trailer, ManifestFormat::Json)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Write `value`, as a line for JSON, returns the number of bytes written
+fn write_record<T: Serialize>(
+    out: &mut impl Write,
+    value: &T,
+    encoding: ManifestFormat,
+) -> Result<u64> {
+    let mut record = encoding.encode(value)?;
+    if encoding == ManifestFormat::Json {
+        record.push(b'\n');
+    }
+    out.write_all(&record)?;
+    Ok(record.len() as u64)
+}
+
+impl ManifestReader for FileManifest {
+    fn node(&self, rel: &Path) -> Result<Cow<'_, Node>> {
+        let &(offset, len) = self
+            .index
+            .get(rel)
+            .ok_or_else(|| Error::MissingNode(rel.to_path_buf()))?;
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut record = vec![0u8; len as usize];
+        file.read_exact(&mut record)?;
+        match ManifestFormat::decode::<FileNode>(&record) {
+            Ok(record) => {
+                check_schema(record.node.schema_version, &self.path)?;
+                Ok(Cow::Owned(record.node.into_owned()))
+            }
+            Err(e) => {
+                if let Ok(v) = ManifestFormat::decode::<VersionedRecord>(&record) {
+                    check_schema(v.node.schema_version, &self.path)?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn is_dir(&self, rel: &Path) -> bool {
+        self.index.contains_key(rel)
+    }
+}
+
+/// The single-file manifest of a manifest folder, if it has one
+pub(crate) fn manifest_file(dir: &Path) -> Option<PathBuf> {
+    ManifestFormat::ALL
+        .iter()
+        .map(|f| dir.join(f.manifest_file()))
+        .find(|p| p.is_file())
+}
+
+/// Whether `dir` holds a manifest, in any layout and format
+pub(crate) fn has_manifest(dir: &Path) -> bool {
+    manifest_file(dir).is_some() || node_file(dir).is_some()
+}
+
+/// Whether the file at `path` is a single-file manifest, rather than an
+/// exported one
+pub(crate) fn is_manifest_file(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; MANIFEST_FILE_MAGIC.len()];
+    Ok(File::open(path)?.read_exact(&mut magic).is_ok() && magic == MANIFEST_FILE_MAGIC)
+}
+
+/// Open the manifest at `path`: a manifest folder in either layout, a
+/// single-file manifest, or a manifest exported as one JSON or CBOR object
+pub fn open_manifest(path: &Path) -> Result<Box<dyn ManifestReader>> {
+    if path.is_file() {
+        if is_manifest_file(path)? {
+            return Ok(Box::new(FileManifest::open(path)?));
+        }
+        return Ok(Box::new(MemoryManifest::from_file(path)?));
+    }
+    match manifest_file(path) {
+        Some(file) => Ok(Box::new(FileManifest::open(&file)?)),
+        None => Ok(Box::new(DiskManifest::new(path))),
+    }
+}
+
+/// Manifest kept in memory, produced by a build that does not store
+#[derive(Default)]
+pub struct MemoryManifest {
+    pub nodes: HashMap<PathBuf, Node>,
+}
+
+impl MemoryManifest {
+    /// Read a manifest exported as a single JSON or CBOR object of nodes
+    /// keyed by directory, "" being the root
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        match ManifestFormat::decode::<HashMap<PathBuf, Node>>(&bytes) {
+            Ok(nodes) => {
+                for node in nodes.values() {
+                    check_schema(node.schema_version, path)?;
+                }
+                Ok(MemoryManifest { nodes })
+            }
+            Err(e) => {
+                if let Ok(nodes) = ManifestFormat::decode::<HashMap<PathBuf, Versioned>>(&bytes) {
+                    for v in nodes.values() {
+                        check_schema(v.schema_version, path)?;
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Read a whole manifest tree in memory
+    pub fn load(reader: &dyn ManifestReader) -> Result<Self> {
+        let mut manifest = MemoryManifest::default();
+        let mut pending = vec![PathBuf::new()];
+        while let Some(rel) = pending.pop() {
+            let node = reader.node(&rel)?.into_owned();
+            for child in &node.children {
+                let sub = rel.join(&child.name);
+                if reader.is_dir(&sub) {
+                    pending.push(sub);
+                }
+            }
+            manifest.nodes.insert(rel, node);
+        }
+        Ok(manifest)
+    }
+}
+
+impl ManifestReader for MemoryManifest {
+    fn node(&self, rel: &Path) -> Result<Cow<'_, Node>> {
+        self.nodes
+            .get(rel)
+            .map(Cow::Borrowed)
+            .ok_or_else(|| Error::MissingNode(rel.to_path_buf()))
+    }
+
+    fn is_dir(&self, rel: &Path) -> bool {
+        self.nodes.contains_key(rel)
+    }
+}
+
+/// Write a test dataset under `dir` from `(path, content)` pairs, a path
+/// ending with `/` is an empty folder
+#[cfg(test)]
+pub(crate) fn write_dataset(dir: &Path, entries: &[(&str, &str)]) {
+    for (path, content) in entries {
+        match path.strip_suffix('/') {
+            Some(folder) => fs::create_dir_all(dir.join(folder)).unwrap(),
+            None => {
+                let path = dir.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, content).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::DatasetHasher;
+
+    /// Nested files and an empty directory, hashed in chunks
+    const LAYOUT: &[(&str, &str)] = &[
+        ("top.txt", "top"),
+        ("empty/", ""),
+        ("sub/deeper/leaf.txt", "leaf"),
+        ("sub/chunked.txt", "content hashed in chunks"),
+    ];
+
+    fn hasher(dir: &Path) -> DatasetHasher {
+        DatasetHasher::new(dir)
+            .method(HashMethod::Blake3)
+            .chunking(Chunking::new(256).unwrap())
+    }
+
+    fn assert_same(expected: &MemoryManifest, actual: &dyn ManifestReader) {
+        let loaded = MemoryManifest::load(actual).unwrap();
+        assert_eq!(loaded.nodes.len(), expected.nodes.len());
+        for (rel, node) in &expected.nodes {
+            assert_eq!(
+                serde_json::to_value(node).unwrap(),
+                serde_json::to_value(&*actual.node(rel).unwrap()).unwrap(),
+                "node {}",
+                rel.display()
+            );
+        }
+    }
+
+    #[test]
+    fn single_file_manifest_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dataset(dir.path(), LAYOUT);
+        let manifest = hasher(dir.path()).store(false).build().unwrap().manifest;
+        assert!(
+            manifest.nodes[Path::new("sub")].children[0]
+                .chunks
+                .is_some()
+        );
+
+        for format in ManifestFormat::ALL {
+            let path = dir.path().join(format.manifest_file());
+            FileManifest::write(&manifest, &path, format).unwrap();
+            assert!(is_manifest_file(&path).unwrap());
+            let file = FileManifest::open(&path).unwrap();
+            assert_eq!(file.encoding(), format);
+            assert_same(&manifest, &file);
+            assert!(matches!(
+                file.node(Path::new("missing")),
+                Err(Error::MissingNode(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn stored_manifests_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dataset(dir.path(), LAYOUT);
+        let expected = hasher(dir.path()).store(false).build().unwrap().manifest;
+        let rush_root = dir.path().join(".rush");
+
+        for format in ManifestFormat::ALL {
+            for layout in [ManifestLayout::Tree, ManifestLayout::File] {
+                hasher(dir.path())
+                    .manifest_format(format)
+                    .manifest_layout(layout)
+                    .build()
+                    .unwrap();
+                let single = manifest_file(&rush_root);
+                match layout {
+                    ManifestLayout::Tree => {
+                        assert_eq!(single, None);
+                        assert_eq!(
+                            node_file(&rush_root),
+                            Some(rush_root.join(format.node_file()))
+                        );
+                    }
+                    ManifestLayout::File => {
+                        assert_eq!(single, Some(rush_root.join(format.manifest_file())));
+                    }
+                }
+                assert_same(&expected, &*open_manifest(&rush_root).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn exported_manifest_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        write_dataset(dir.path(), LAYOUT);
+        let expected = hasher(dir.path()).store(false).build().unwrap().manifest;
+        for format in ManifestFormat::ALL {
+            let path = dir.path().join("exported");
+            fs::write(&path, format.encode(&expected.nodes).unwrap()).unwrap();
+            assert!(!is_manifest_file(&path).unwrap());
+            assert_same(&expected, &*open_manifest(&path).unwrap());
+        }
+    }
 }